@@ -1,52 +1,132 @@
-use anyhow::{Result, bail, ensure};
+use anyhow::{Result, bail};
 use nested_workspace::{
-    CargoSubcommand, Source, all_nested_workspace_roots, build_cargo_command, parse_cargo_command,
-    parse_cargo_subcommand, run_cargo_subcommand_on_all_nested_workspace_roots,
+    CargoCommand, CargoSubcommand, DRY_RUN_ENV, Error, JUNIT_ENV, OUTPUT_ENV, OutputMode,
+    TIMEOUT_ENV, all_nested_workspace_roots, lock_report, merged_metadata, parse_cargo_command,
+    parse_cargo_subcommand, parse_duration, run_cargo_subcommand_on_all_nested_workspace_roots,
+    run_cargo_subcommand_on_current_workspace, run_program_on_all_nested_workspace_roots,
+    run_program_on_current_workspace, verify,
+};
+use std::{
+    env::{args, current_dir},
+    process::ExitCode,
 };
-use std::env::{args, current_dir};
 
 const USAGE: &str = concat!(
-    "Usage: cargo nested [OPTIONS or Cargo SUBCOMMAND]\n",
+    "Usage: cargo nested [OPTIONS] [Cargo SUBCOMMAND]\n",
+    "       cargo nested [OPTIONS] exec [--] <PROGRAM> [ARGS]...\n",
+    "       cargo nested lock-report\n",
+    "       cargo nested metadata [ARGS]...\n",
+    "       cargo nested verify\n",
     "\n",
     "Options:\n",
-    "      --list     List current nested workspaces\n",
-    "  -h, --help     Print help\n",
-    "  -V, --version  Print version\n",
+    "      --list                List current nested workspaces\n",
+    "      --dry-run             Print the commands that would be run without running them\n",
+    "      --nested-only         Do not run on the current package or workspace\n",
+    "      --no-recurse          Run only on the current package or workspace\n",
+    "      --junit <PATH>        Write a JUnit XML report of nested workspaces' test results to PATH\n",
+    "      --output <MODE>       Show nested workspaces' output `delimited` (default) or `prefixed`\n",
+    "      --timeout <DURATION>  Kill a nested workspace's command if it runs longer than DURATION\n",
+    "                            (e.g., `90s` or `10m`), overriding any timeout in its metadata\n",
+    "  -h, --help                Print help\n",
+    "  -V, --version             Print version\n",
     "\n",
     "If a Cargo SUBCOMMAND is passed, it is run on the current package and all nested workspaces.\n",
     "\n",
     "For example, the following command runs `cargo clean` on the current package and all nested workspaces:\n",
     "\n",
-    "    cargo nested clean"
+    "    cargo nested clean\n",
+    "\n",
+    "If `exec` is passed, PROGRAM is run with ARGS on the current package and all nested workspaces.\n",
+    "PROGRAM is passed the directory it runs in and the containing package's name (if any) in the\n",
+    "`NESTED_WORKSPACE_ROOT` and `NESTED_WORKSPACE_PACKAGE` environment variables.\n",
+    "\n",
+    "If `lock-report` is passed, crates resolved to different versions in the Cargo.lock files of the\n",
+    "current workspace and all nested workspaces are listed.\n",
+    "\n",
+    "If `metadata` is passed, `cargo metadata` is run with ARGS on the current workspace and all nested\n",
+    "workspaces, and the results are printed as one JSON document.\n",
+    "\n",
+    "If `verify` is passed, the `nested_workspace` metadata of the current workspace and all nested\n",
+    "workspaces is checked for problems."
 );
 
+/// Options that control which packages and workspaces are run on
+#[derive(Default)]
+struct Scope {
+    nested_only: bool,
+    no_recurse: bool,
+}
+
 enum Action {
     List,
     Help,
     Version,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report(&error);
+            exit_code(&error)
+        }
+    }
+}
+
+fn run() -> Result<()> {
     let args = args().collect::<Vec<_>>();
 
-    let Some((subcommand, args)) = parse_args(&args)? else {
+    let Some((scope, subcommand, args)) = parse_args(&args)? else {
         return Ok(());
     };
 
+    let current_dir = current_dir()?;
+
+    if let CargoSubcommand::Other(other) = &subcommand
+        && (other == "lock-report" || other == "verify")
+    {
+        if let Some(arg) = args.first() {
+            bail!("unexpected argument: {arg}\n\n{USAGE}")
+        }
+        return if other == "lock-report" {
+            lock_report(&current_dir)
+        } else {
+            verify(&current_dir)
+        };
+    }
+
+    if matches!(&subcommand, CargoSubcommand::Other(other) if other == "metadata") {
+        return merged_metadata(&current_dir, args);
+    }
+
+    if let Some(program_and_args) = exec_program_and_args(&subcommand, args)? {
+        if !scope.nested_only {
+            run_program_on_current_workspace(program_and_args)?;
+        }
+        if !scope.no_recurse {
+            run_program_on_all_nested_workspace_roots(program_and_args, &current_dir, false)?;
+        }
+        return Ok(());
+    }
+
     // smoelius: Run on current package or workspace.
-    let mut command = build_cargo_command(Source::CargoNested, None, &subcommand, args)?;
-    let status = command.status()?;
-    ensure!(status.success(), "command failed: {command:?}");
+    if !scope.nested_only {
+        run_cargo_subcommand_on_current_workspace(&subcommand, args)?;
+    }
 
     // smoelius: Run on all nested workspaces.
-    let current_dir = current_dir()?;
-    run_cargo_subcommand_on_all_nested_workspace_roots(&subcommand, args, &current_dir, false)?;
+    if !scope.no_recurse {
+        run_cargo_subcommand_on_all_nested_workspace_roots(&subcommand, args, &current_dir, false)?;
+    }
 
     Ok(())
 }
 
-fn parse_args(args: &[String]) -> Result<Option<(CargoSubcommand, &[String])>> {
-    let Some((subcommand, args)) = parse_cargo_command(args)? else {
+fn parse_args(args: &[String]) -> Result<Option<(Scope, CargoSubcommand, &[String])>> {
+    let Some(CargoCommand {
+        subcommand, args, ..
+    }) = parse_cargo_command(args)?
+    else {
         bail!("failed to parse `cargo nested` arguments: {args:?}")
     };
 
@@ -54,42 +134,164 @@ fn parse_args(args: &[String]) -> Result<Option<(CargoSubcommand, &[String])>> {
         bail!("failed to parse `cargo nested` arguments: {subcommand} {args:?}")
     }
 
-    if parse_cargo_nested_args(args)? {
+    let Some((scope, args)) = parse_cargo_nested_args(args)? else {
         return Ok(None);
-    }
+    };
 
     let (subcommand, args) = parse_cargo_subcommand(args)?;
 
-    Ok(Some((subcommand, args)))
+    Ok(Some((scope, subcommand, args)))
 }
 
-fn parse_cargo_nested_args(args: &[String]) -> Result<bool> {
-    let mut args = args.iter();
+/// Parse the options preceding the Cargo subcommand, if any
+///
+/// Returns which packages and workspaces to run on, and the Cargo subcommand and its arguments. Or
+/// returns `None` if an action like `--list` was performed instead.
+fn parse_cargo_nested_args(mut args: &[String]) -> Result<Option<(Scope, &[String])>> {
+    let mut scope = Scope::default();
+
+    while let Some((arg, rest)) = args.split_first() {
+        if !arg.starts_with('-') {
+            if scope.nested_only && scope.no_recurse {
+                bail!("`--nested-only` and `--no-recurse` cannot be used together\n\n{USAGE}")
+            }
+            return Ok(Some((scope, args)));
+        }
+
+        args = rest;
+
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+
+        let action = match name {
+            "--list" => Action::List,
+            "-h" | "--help" => Action::Help,
+            "-V" | "--version" => Action::Version,
+            "--dry-run" | "--nested-only" | "--no-recurse" => {
+                if value.is_some() {
+                    bail!("unrecognized argument: {arg}\n\n{USAGE}")
+                }
+                match name {
+                    "--dry-run" => set_var(DRY_RUN_ENV, "1"),
+                    "--nested-only" => scope.nested_only = true,
+                    _ => scope.no_recurse = true,
+                }
+                continue;
+            }
+            "--junit" => {
+                let value = option_value(name, value, &mut args)?;
+                set_var(JUNIT_ENV, value);
+                continue;
+            }
+            "--output" => {
+                let value = option_value(name, value, &mut args)?;
+                value.parse::<OutputMode>()?;
+                set_var(OUTPUT_ENV, value);
+                continue;
+            }
+            "--timeout" => {
+                let value = option_value(name, value, &mut args)?;
+                parse_duration(value)?;
+                set_var(TIMEOUT_ENV, value);
+                continue;
+            }
+            _ => bail!("unrecognized argument: {arg}\n\n{USAGE}"),
+        };
+
+        if value.is_some() {
+            bail!("unrecognized argument: {arg}\n\n{USAGE}")
+        }
+
+        if let Some(arg) = args.first() {
+            bail!("unexpected argument: {arg}\n\n{USAGE}")
+        }
+
+        match action {
+            Action::List => list_nested_workspaces()?,
+            Action::Help => println!("{USAGE}"),
+            Action::Version => println!("cargo-nested {}", env!("CARGO_PKG_VERSION")),
+        }
+
+        return Ok(None);
+    }
 
-    let Some(arg) = args.next() else { bail!(USAGE) };
+    bail!(USAGE)
+}
 
-    if !arg.starts_with('-') {
-        return Ok(false);
+/// Print `error` the way Cargo does, i.e., prefixed with `error: ` and followed by its causes
+fn report(error: &anyhow::Error) {
+    eprintln!("error: {error}");
+    let mut causes = error.chain().skip(1).peekable();
+    if causes.peek().is_some() {
+        eprintln!("\nCaused by:");
+        for cause in causes {
+            eprintln!("  {cause}");
+        }
     }
+}
 
-    let action = match arg.as_str() {
-        "--list" => Action::List,
-        "-h" | "--help" => Action::Help,
-        "-V" | "--version" => Action::Version,
-        _ => bail!("unrecognized argument: {arg}\n\n{USAGE}"),
+// smoelius: `cargo nested` stops at the first command that fails. So there is at most one failed
+// command, and its exit code is used, e.g., 101 if tests failed. If the command was terminated by
+// a signal, the exit code is 128 plus the signal number, as a shell would report. Any other error
+// results in exit code 1.
+fn exit_code(error: &anyhow::Error) -> ExitCode {
+    let Some(Error::CommandFailed { status, .. }) = error.downcast_ref() else {
+        return ExitCode::FAILURE;
     };
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(status) {
+        return u8::try_from(128 + signal).map_or(ExitCode::FAILURE, ExitCode::from);
+    }
+    status
+        .code()
+        .and_then(|code| u8::try_from(code).ok())
+        .filter(|&code| code != 0)
+        .map_or(ExitCode::FAILURE, ExitCode::from)
+}
 
-    if let Some(arg) = args.next() {
-        bail!("unexpected argument: {arg}\n\n{USAGE}")
+/// If the subcommand is `exec`, return the program and its arguments
+///
+/// The program may be preceded by `--`, e.g., `cargo nested exec -- git status`.
+fn exec_program_and_args<'a>(
+    subcommand: &CargoSubcommand,
+    args: &'a [String],
+) -> Result<Option<&'a [String]>> {
+    if !matches!(subcommand, CargoSubcommand::Other(other) if other == "exec") {
+        return Ok(None);
     }
+    let program_and_args = args.strip_prefix(&[String::from("--")]).unwrap_or(args);
+    if program_and_args.is_empty() {
+        bail!("`exec` requires a program\n\n{USAGE}");
+    }
+    Ok(Some(program_and_args))
+}
 
-    match action {
-        Action::List => list_nested_workspaces()?,
-        Action::Help => println!("{USAGE}"),
-        Action::Version => println!("cargo-nested {}", env!("CARGO_PKG_VERSION")),
+/// Get the value of option `name`, either from `value` (i.e., `--name=value`) or from the next
+/// argument (i.e., `--name value`)
+fn option_value<'a>(
+    name: &str,
+    value: Option<&'a str>,
+    args: &mut &'a [String],
+) -> Result<&'a str> {
+    if let Some(value) = value {
+        return Ok(value);
     }
+    let Some((value, rest)) = args.split_first() else {
+        bail!("`{name}` requires a value\n\n{USAGE}")
+    };
+    *args = rest;
+    Ok(value)
+}
 
-    Ok(true)
+// smoelius: Options are communicated to the library through environment variables. This way, they
+// also affect any processes `cargo-nested` spawns.
+fn set_var(key: &str, value: &str) {
+    // SAFETY: `cargo-nested` is single threaded at this point.
+    unsafe {
+        std::env::set_var(key, value);
+    }
 }
 
 fn list_nested_workspaces() -> Result<()> {
@@ -0,0 +1,3 @@
+fn main() {
+    nested_workspace::build().tests().unwrap();
+}
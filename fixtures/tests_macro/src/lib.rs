@@ -0,0 +1,2 @@
+#[cfg(test)]
+nested_workspace::tests!();
@@ -1,22 +1,34 @@
 use crate::{
-    Source,
+    Error, Source,
     cargo_nested::CARGO_NESTED_ENV,
     reentrancy_guard::{dependent_from_package_name, reentrancy_guard_from_package_name},
+    sanitize::{EnvPolicy, sanitize},
 };
-use anyhow::{Result, bail};
-use elaborate::std::{ffi::OsStrContext, path::PathContext};
+use anyhow::{Context, Result, bail};
+use elaborate::std::{env::var_wc, ffi::OsStrContext, path::PathContext, process::CommandContext};
 use std::{
+    collections::{HashMap, HashSet},
     ffi::{OsStr, OsString},
     fmt::Debug,
     path::Path,
     process::{Command, id},
     sync::LazyLock,
 };
-use sysinfo::{Pid, ProcessRefreshKind, RefreshKind, System, UpdateKind};
+use sysinfo::{Pid, Process, ProcessRefreshKind, RefreshKind, System, UpdateKind};
 
+/// Environment variable through which `cargo nested exec` passes the directory the program is run in
+pub const ROOT_ENV: &str = "NESTED_WORKSPACE_ROOT";
+
+/// Environment variable through which `cargo nested exec` passes the containing package's name
+pub const PACKAGE_ENV: &str = "NESTED_WORKSPACE_PACKAGE";
+
+#[non_exhaustive]
 pub enum CargoSubcommand {
     Build,
     Check,
+    Clippy,
+    /// `cargo nextest`, i.e., [cargo-nextest](https://nexte.st)
+    Nextest,
     Run,
     Test,
     Other(OsString),
@@ -27,6 +39,8 @@ impl CargoSubcommand {
         match self {
             CargoSubcommand::Build => OsStr::new("build"),
             CargoSubcommand::Check => OsStr::new("check"),
+            CargoSubcommand::Clippy => OsStr::new("clippy"),
+            CargoSubcommand::Nextest => OsStr::new("nextest"),
             CargoSubcommand::Run => OsStr::new("run"),
             CargoSubcommand::Test => OsStr::new("test"),
             CargoSubcommand::Other(other) => other,
@@ -41,34 +55,69 @@ impl std::fmt::Display for CargoSubcommand {
 }
 
 #[derive(Clone)]
+#[non_exhaustive]
 pub struct PackageContext {
     pub name: String,
     pub dependent: bool,
+    pub env: EnvPolicy,
+}
+
+impl PackageContext {
+    /// Create a context for the package named `name`, with the default [`EnvPolicy`]
+    #[must_use]
+    pub fn new(name: impl Into<String>, dependent: bool) -> Self {
+        Self {
+            name: name.into(),
+            dependent,
+            env: EnvPolicy::default(),
+        }
+    }
 }
 
 static SYSTEM: LazyLock<System> = LazyLock::new(|| {
     System::new_with_specifics(
-        RefreshKind::nothing()
-            .with_processes(ProcessRefreshKind::nothing().with_cmd(UpdateKind::Always)),
+        RefreshKind::nothing().with_processes(
+            ProcessRefreshKind::nothing()
+                .with_cmd(UpdateKind::Always)
+                .with_cwd(UpdateKind::Always),
+        ),
     )
 });
 
-pub fn parent_cargo_command() -> Result<(CargoSubcommand, &'static [OsString])> {
+pub fn parent_cargo_command() -> Result<(CargoSubcommand, Vec<OsString>)> {
     let mut id = id();
     loop {
-        let (parent_id, command) = parent_command(id)?;
-        match parse_cargo_command(command)? {
-            Some((subcommand, args)) => {
-                return Ok((subcommand, args));
-            }
-            None => {
-                id = parent_id;
-            }
+        let process = parent_process(id).context(Error::ParentCommandNotFound)?;
+        let parent_id = process.pid().as_u32();
+        let Some(command) = parse_cargo_command(process.cmd())? else {
+            id = parent_id;
+            continue;
+        };
+        // smoelius: `cargo clippy` runs `cargo-clippy`, which runs `cargo check`. So if the parent
+        // command is `cargo check`, check whether it was run by `cargo-clippy`.
+        if matches!(command.subcommand, CargoSubcommand::Check)
+            && let Some(clippy_command) = parent_process(parent_id)
+                .ok()
+                .and_then(|process| parse_cargo_command(process.cmd()).ok().flatten())
+            && matches!(clippy_command.subcommand, CargoSubcommand::Clippy)
+        {
+            let args = clippy_command
+                .forwardable_global_args()
+                .into_iter()
+                .chain(clippy_command.args.iter().cloned())
+                .collect();
+            return Ok((CargoSubcommand::Clippy, args));
         }
+        let global_args = command.forwardable_global_args();
+        let (subcommand, args) = match command.subcommand {
+            CargoSubcommand::Other(name) => expand_alias(process, &name, command.args)?,
+            subcommand => (subcommand, command.args.to_vec()),
+        };
+        return Ok((subcommand, global_args.into_iter().chain(args).collect()));
     }
 }
 
-fn parent_command(id: u32) -> Result<(u32, &'static [OsString])> {
+fn parent_process(id: u32) -> Result<&'static Process> {
     let Some(process) = SYSTEM.process(Pid::from_u32(id)) else {
         bail!("failed to get process with id {id}");
     };
@@ -78,26 +127,204 @@ fn parent_command(id: u32) -> Result<(u32, &'static [OsString])> {
     let Some(parent_process) = SYSTEM.process(parent_id) else {
         bail!("failed to get process with id {parent_id}");
     };
-    let cmd = parent_process.cmd();
-    Ok((parent_id.as_u32(), cmd))
+    Ok(parent_process)
+}
+
+/// Expand `name` if it is an alias defined in a Cargo configuration file, e.g.:
+///
+/// ```toml
+/// [alias]
+/// ci-check = "check --all-targets"
+/// ```
+///
+/// `process` is the Cargo process that was passed `name` and `args`. Aliases are read from
+/// `CARGO_ALIAS_<NAME>` environment variables and from the output of `cargo --list` run in
+/// `process`'s working directory, so that the same configuration files apply. If `name` is not an
+/// alias, `Other(name)` and `args` are returned unchanged.
+fn expand_alias(
+    process: &Process,
+    name: &OsStr,
+    args: &[OsString],
+) -> Result<(CargoSubcommand, Vec<OsString>)> {
+    let unchanged = || Ok((CargoSubcommand::Other(name.to_owned()), args.to_vec()));
+
+    // smoelius: Only `cargo` itself expands aliases, not, e.g., `cargo-clippy`.
+    let Some(cargo) = process.cmd().first() else {
+        return unchanged();
+    };
+    if Path::new(cargo).file_stem_wc().ok() != Some(OsStr::new("cargo")) {
+        return unchanged();
+    }
+
+    let mut command = Command::new(cargo);
+    command.arg("--list");
+    if let Some(cwd) = process.cwd() {
+        command.current_dir(cwd);
+    }
+    let output = command.output_wc()?;
+    if !output.status.success() {
+        return unchanged();
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let aliases = stdout
+        .lines()
+        .filter_map(|line| {
+            let (name, description) = line.trim_start().split_once(char::is_whitespace)?;
+            let expansion = description.trim_start().strip_prefix("alias: ")?;
+            Some((name, expansion))
+        })
+        .collect::<HashMap<_, _>>();
+
+    // smoelius: An alias can expand to another alias. Keep track of the names seen to avoid
+    // looping forever.
+    let mut seen = HashSet::new();
+    let mut name = name.to_owned();
+    let mut args = args.to_vec();
+    // smoelius: Aliases defined in `CARGO_ALIAS_<NAME>` environment variables take precedence and
+    // are not listed by `cargo --list`. A build script inherits Cargo's environment, so they can be
+    // read directly.
+    let lookup = |name: &str| {
+        let key = format!("CARGO_ALIAS_{}", name.to_uppercase().replace('-', "_"));
+        var_wc(key)
+            .ok()
+            .or_else(|| aliases.get(name).map(ToString::to_string))
+    };
+    while let Some(expansion) = name.to_str_wc().ok().and_then(lookup) {
+        if !seen.insert(name.clone()) {
+            bail!("alias `{}` is defined recursively", name.display());
+        }
+        let expansion = expansion
+            .split_whitespace()
+            .map(OsString::from)
+            .chain(args)
+            .collect::<Vec<_>>();
+        let (subcommand, rest) = parse_cargo_subcommand(&expansion)?;
+        let rest = rest.to_vec();
+        match subcommand {
+            CargoSubcommand::Other(other) => {
+                name = other;
+                args = rest;
+            }
+            subcommand => return Ok((subcommand, rest)),
+        }
+    }
+    Ok((CargoSubcommand::Other(name), args))
+}
+
+/// A Cargo command line, e.g., `cargo +nightly -v check --offline`
+#[non_exhaustive]
+pub struct CargoCommand<'a, T> {
+    /// The toolchain selected with `+toolchain`, if any
+    ///
+    /// The toolchain is not passed to commands run on nested workspaces. Like `RUSTUP_TOOLCHAIN`, it
+    /// would override a nested workspace's `rust-toolchain` file.
+    pub toolchain: Option<String>,
+    /// The global options preceding the subcommand, e.g., `-v`
+    pub global_args: &'a [T],
+    pub subcommand: CargoSubcommand,
+    /// The arguments following the subcommand
+    pub args: &'a [T],
+}
+
+impl<T: AsRef<OsStr>> CargoCommand<'_, T> {
+    /// Return the global options that can be passed to a Cargo command run on a nested workspace
+    ///
+    /// `-C` is excluded because it would change the directory the command runs in. `-Z` is excluded
+    /// because a nested workspace need not use a nightly toolchain.
+    #[must_use]
+    pub fn forwardable_global_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        let mut iter = self.global_args.iter().map(AsRef::as_ref);
+        while let Some(arg) = iter.next() {
+            let value = if global_option_takes_value(arg) {
+                iter.next()
+            } else {
+                None
+            };
+            if starts_with(arg, "-C") || starts_with(arg, "-Z") {
+                continue;
+            }
+            args.push(arg.to_owned());
+            args.extend(value.map(OsStr::to_owned));
+        }
+        args
+    }
+}
+
+/// Whether `arg` is a global option whose value is the next argument (e.g., `--config` in
+/// `--config net.offline=true`)
+fn global_option_takes_value(arg: &OsStr) -> bool {
+    ["--color", "--config", "--explain", "-C", "-Z"]
+        .iter()
+        .any(|option| arg == *option)
+}
+
+fn starts_with(arg: &OsStr, prefix: &str) -> bool {
+    arg.as_encoded_bytes().starts_with(prefix.as_bytes())
+}
+
+/// Whether `args` contain `--offline`, `--frozen`, or `--config net.offline=true`
+#[cfg(not(feature = "__disable_offline_check"))]
+pub fn offline<T: AsRef<OsStr>>(args: &[T]) -> bool {
+    let mut iter = args.iter().map(AsRef::as_ref);
+    while let Some(arg) = iter.next() {
+        if arg == "--offline" || arg == "--frozen" {
+            return true;
+        }
+        let arg = arg.to_string_lossy();
+        let config = if arg == "--config" {
+            iter.next()
+                .map(|value| value.to_string_lossy().into_owned())
+        } else {
+            arg.strip_prefix("--config=").map(ToOwned::to_owned)
+        };
+        if config.is_some_and(|config| config.replace(' ', "") == "net.offline=true") {
+            return true;
+        }
+    }
+    false
 }
 
 #[expect(clippy::similar_names)]
 pub fn parse_cargo_command<T: AsRef<OsStr> + Debug>(
     args: &[T],
-) -> Result<Option<(CargoSubcommand, &[T])>> {
-    if args.is_empty()
-        || !{
-            let arg0 = args[0].as_ref();
-            let path = Path::new(&arg0);
-            path.file_stem_wc()
-                .and_then(OsStr::to_str_wc)
-                .is_ok_and(|file_stem| file_stem == "cargo" || file_stem.starts_with("cargo-"))
-        }
-    {
+) -> Result<Option<CargoCommand<'_, T>>> {
+    let Some(arg0) = args.first() else {
+        return Ok(None);
+    };
+    let Ok(file_stem) = Path::new(arg0.as_ref())
+        .file_stem_wc()
+        .and_then(OsStr::to_str_wc)
+    else {
+        return Ok(None);
+    };
+    if file_stem != "cargo" && !file_stem.starts_with("cargo-") {
         return Ok(None);
     }
-    parse_cargo_subcommand(&args[1..]).map(Some)
+    let mut rest = &args[1..];
+    let toolchain = rest
+        .first()
+        .and_then(|arg| arg.as_ref().to_str_wc().ok())
+        .and_then(|arg| arg.strip_prefix('+'))
+        .map(ToOwned::to_owned);
+    if toolchain.is_some() {
+        rest = &rest[1..];
+    }
+    let mut n_global_args = 0;
+    while let Some(arg) = rest.get(n_global_args).map(AsRef::as_ref) {
+        if !starts_with(arg, "-") {
+            break;
+        }
+        n_global_args += if global_option_takes_value(arg) { 2 } else { 1 };
+    }
+    let (global_args, rest) = rest.split_at(n_global_args.min(rest.len()));
+    let (subcommand, args) = parse_cargo_subcommand(rest)?;
+    Ok(Some(CargoCommand {
+        toolchain,
+        global_args,
+        subcommand,
+        args,
+    }))
 }
 
 #[expect(clippy::similar_names)]
@@ -110,10 +337,13 @@ pub fn parse_cargo_subcommand<T: AsRef<OsStr> + Debug>(
     let arg0 = args[0].as_ref();
     #[allow(clippy::allow_attributes, clippy::disallowed_methods)]
     let subcommand = match arg0.to_str() {
-        Some("build") => CargoSubcommand::Build,
-        Some("check") => CargoSubcommand::Check,
-        Some("run") => CargoSubcommand::Run,
-        Some("test") => CargoSubcommand::Test,
+        // smoelius: `b`, `c`, `r`, and `t` are Cargo's built-in aliases.
+        Some("build" | "b") => CargoSubcommand::Build,
+        Some("check" | "c") => CargoSubcommand::Check,
+        Some("clippy") => CargoSubcommand::Clippy,
+        Some("nextest") => CargoSubcommand::Nextest,
+        Some("run" | "r") => CargoSubcommand::Run,
+        Some("test" | "t") => CargoSubcommand::Test,
         _ => CargoSubcommand::Other(arg0.to_owned()),
     };
     Ok((subcommand, &args[1..]))
@@ -135,6 +365,15 @@ pub fn build_cargo_command<T: AsRef<OsStr> + Debug>(
         (Source::BuildScript, CargoSubcommand::Check) => {
             (OsStr::new("check"), build_or_check_args())
         }
+        // smoelius: If `cargo clippy` caused the build script to be run, run `cargo clippy`. Forward
+        // the lint flags, i.e., the arguments following `--`.
+        (Source::BuildScript, CargoSubcommand::Clippy) => {
+            let mut clippy_args = build_or_check_args();
+            if let Some(index) = args.iter().position(|arg| arg.as_ref() == "--") {
+                clippy_args.extend(args[index..].iter().map(OsString::from));
+            }
+            (OsStr::new("clippy"), clippy_args)
+        }
         (Source::BuildScript, _subcommand_other_than_check) => {
             (OsStr::new("build"), build_or_check_args())
         }
@@ -147,6 +386,27 @@ pub fn build_cargo_command<T: AsRef<OsStr> + Debug>(
                 .collect();
             (OsStr::new("test"), args)
         }
+        // smoelius: `cargo nextest run` is treated like `cargo test`. The arguments following `run`
+        // are forwarded, e.g., filters and `--profile`.
+        (Source::Test, CargoSubcommand::Nextest) => {
+            let Some((run, args)) = args.split_first() else {
+                bail!("{source} unexpectedly invoked `cargo nextest` without a command");
+            };
+            if !matches!(run.as_ref().to_str_wc(), Ok("run" | "r")) {
+                bail!(
+                    "{source} unexpectedly invoked `cargo nextest {}`",
+                    run.as_ref().display()
+                );
+            }
+            let args = [OsString::from("run"), OsString::from("--workspace")]
+                .into_iter()
+                .chain(filter_package_and_workspace(
+                    package.map(|package| package.name.as_str()),
+                    args,
+                ))
+                .collect();
+            (OsStr::new("nextest"), args)
+        }
         // smoelius: Do not pass `--workspace` to all Cargo subcommands, because not all subcommands
         // accept such an option. `cargo fmt` is an example.
         (Source::CargoNested, _) => {
@@ -157,9 +417,11 @@ pub fn build_cargo_command<T: AsRef<OsStr> + Debug>(
     };
     command.arg(subcommand);
     command.args(args);
-    command.env_remove("CARGO");
-    command.env_remove("RUSTC");
-    command.env_remove("RUSTUP_TOOLCHAIN");
+    sanitize(
+        &mut command,
+        source,
+        package.map_or(&EnvPolicy::default(), |package| &package.env),
+    );
     match source {
         Source::CargoNested => {
             command.env(CARGO_NESTED_ENV, "1");
@@ -180,6 +442,34 @@ pub fn build_cargo_command<T: AsRef<OsStr> + Debug>(
     Ok(command)
 }
 
+/// Build a command that runs the program named by the first element of `program_and_args` in `dir`,
+/// as with `cargo nested exec`
+///
+/// The command's environment is sanitized as for `cargo nested`, and `dir` and the containing
+/// package's name (if any) are exposed through [`ROOT_ENV`] and [`PACKAGE_ENV`].
+pub fn build_exec_command<T: AsRef<OsStr> + Debug>(
+    package: Option<&PackageContext>,
+    dir: &Path,
+    program_and_args: &[T],
+) -> Result<Command> {
+    let Some((program, args)) = program_and_args.split_first() else {
+        bail!("no program to execute");
+    };
+    let mut command = Command::new(program);
+    command.args(args);
+    sanitize(
+        &mut command,
+        Source::CargoNested,
+        package.map_or(&EnvPolicy::default(), |package| &package.env),
+    );
+    command.env(CARGO_NESTED_ENV, "1");
+    command.env(ROOT_ENV, dir);
+    if let Some(package) = package {
+        command.env(PACKAGE_ENV, &package.name);
+    }
+    Ok(command)
+}
+
 fn build_or_check_args() -> Vec<OsString> {
     ["-vv", "--offline", "--workspace"]
         .iter()
@@ -211,3 +501,80 @@ fn filter_package_and_workspace<T: AsRef<OsStr> + Debug>(
     }
     args_out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{CargoSubcommand, PackageContext, build_cargo_command};
+    use crate::Source;
+    use std::ffi::OsStr;
+
+    fn nextest_args(args: &[&str]) -> anyhow::Result<Vec<String>> {
+        let package = PackageContext::new("containing", false);
+        let command = build_cargo_command(
+            Source::Test,
+            Some(&package),
+            &CargoSubcommand::Nextest,
+            args,
+        )?;
+        assert_eq!(OsStr::new("cargo"), command.get_program());
+        Ok(command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn nextest_run() {
+        for run in ["run", "r"] {
+            assert_eq!(
+                ["nextest", "run", "--workspace"].as_slice(),
+                nextest_args(&[run]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn nextest_other_subcommands() {
+        let error = nextest_args(&["list"]).unwrap_err();
+        assert_eq!(
+            "test unexpectedly invoked `cargo nextest list`",
+            error.to_string()
+        );
+
+        let error = nextest_args(&[]).unwrap_err();
+        assert_eq!(
+            "test unexpectedly invoked `cargo nextest` without a command",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn nextest_package_and_workspace() {
+        assert_eq!(
+            ["nextest", "run", "--workspace", "-p", "other"].as_slice(),
+            nextest_args(&["run", "-p", "containing", "--workspace", "-p", "other"]).unwrap()
+        );
+        assert_eq!(
+            ["nextest", "run", "--workspace"].as_slice(),
+            nextest_args(&["run", "--package", "containing"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn nextest_profile_and_filters() {
+        assert_eq!(
+            [
+                "nextest",
+                "run",
+                "--workspace",
+                "--profile",
+                "ci",
+                "it_works",
+                "--",
+                "--ignored"
+            ]
+            .as_slice(),
+            nextest_args(&["run", "--profile", "ci", "it_works", "--", "--ignored"]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use crate::util::{StripCurrentDir, command_line};
+use anyhow::Result;
+use elaborate::std::env::var_wc;
+use std::{io::Write, path::Path, process::Command};
+
+pub const DRY_RUN_ENV: &str = "NESTED_WORKSPACE_DRY_RUN";
+
+pub fn enabled() -> bool {
+    var_wc(DRY_RUN_ENV).is_ok_and(|value| value != "0")
+}
+
+/// Print `command`, its working directory `dir`, and the changes it makes to the environment
+///
+/// Example output:
+///
+/// ```text
+/// Would run `cargo clean` in `nested_workspace_a`
+///     unset CARGO
+///     set NESTED_WORKSPACE_CARGO_NESTED=1
+/// ```
+pub fn print_command(command: &Command, dir: &Path) -> Result<()> {
+    let mut dir = dir.strip_current_dir();
+    if dir.as_os_str().is_empty() {
+        dir = Path::new(".");
+    }
+    let mut stdout = std::io::stdout().lock();
+    writeln!(
+        stdout,
+        "Would run `{}` in `{}`",
+        command_line(command),
+        dir.display()
+    )?;
+    let mut envs = command.get_envs().collect::<Vec<_>>();
+    envs.sort_by_key(|&(key, value)| (value.is_some(), key));
+    for (key, value) in envs {
+        match value {
+            Some(value) => writeln!(stdout, "    set {}={}", key.display(), value.display())?,
+            None => writeln!(stdout, "    unset {}", key.display())?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use crate::util::StripCurrentDir;
+use std::{
+    fmt::{Display, Formatter},
+    path::PathBuf,
+    process::ExitStatus,
+};
+
+/// Errors that library consumers may want to distinguish
+///
+/// Most public functions return [`anyhow::Result`]s. To determine whether an error is one of the
+/// following, use [`anyhow::Error::downcast_ref`], e.g.:
+///
+/// ```
+/// # fn f(error: &anyhow::Error) {
+/// if let Some(nested_workspace::Error::CommandFailed { status, .. }) = error.downcast_ref() {
+///     std::process::exit(status.code().unwrap_or(1));
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A containing package's build script was reentered, i.e., a nested workspace depends on the
+    /// containing package, but its root is not marked `dependent`
+    CycleDetected { package: String },
+    /// A nested workspace depends on its containing package, but its root is not marked
+    /// `dependent`
+    ///
+    /// Unlike [`Error::CycleDetected`], this error is detected before anything is run on the
+    /// nested workspace.
+    UndeclaredDependent {
+        package: String,
+        root: PathBuf,
+        pattern: String,
+    },
+    /// A package has no `nested_workspace` metadata
+    NoMetadata { manifest_path: PathBuf },
+    /// A root in the `nested_workspace` metadata matches nothing, and the metadata sets
+    /// `strict = true`
+    UnmatchedRoot {
+        manifest_path: PathBuf,
+        pattern: String,
+    },
+    /// A path passed to [`crate::Builder::root`] is not a nested workspace root
+    UnknownRoot { path: PathBuf },
+    /// A command run on the current workspace or a nested workspace exited unsuccessfully
+    CommandFailed {
+        /// The command, formatted with [`std::fmt::Debug`]
+        command: String,
+        status: ExitStatus,
+    },
+    /// The Cargo command that caused a build script or test to be run could not be found
+    ParentCommandNotFound,
+    /// Any other error
+    Other(anyhow::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CycleDetected { .. } => {
+                write!(f, "cycle detected: cannot run on nested workspaces")
+            }
+            Self::UndeclaredDependent {
+                package,
+                root,
+                pattern,
+            } => write!(
+                f,
+                "cycle detected: `{}` depends on `{package}`; set `dependent = true` for \
+                 `{pattern}` in the `nested_workspace` metadata of `{package}`",
+                root.strip_current_dir().display()
+            ),
+            Self::NoMetadata { manifest_path } => write!(
+                f,
+                "package at `{}` has no `nested_workspace` metadata",
+                manifest_path.display()
+            ),
+            Self::UnmatchedRoot {
+                manifest_path,
+                pattern,
+            } => write!(
+                f,
+                "root `{pattern}` in `{}` matches nothing",
+                manifest_path.strip_current_dir().display()
+            ),
+            Self::UnknownRoot { path } => {
+                write!(f, "`{}` is not a nested workspace root", path.display())
+            }
+            Self::CommandFailed { command, .. } => write!(f, "command failed: {command}"),
+            Self::ParentCommandNotFound => write!(f, "failed to find parent Cargo command"),
+            Self::Other(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Other(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+// smoelius: Internally, errors are `anyhow::Error`s. If one wraps an `Error` (e.g., because it was
+// created with `Error::into`), unwrap it. Otherwise, wrap the `anyhow::Error` in `Error::Other`.
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        error.downcast::<Self>().unwrap_or_else(Self::Other)
+    }
+}
@@ -0,0 +1,89 @@
+use crate::util::command_line;
+use anyhow::Result;
+use elaborate::std::{
+    env::var_wc,
+    fs::{
+        DirEntryContext, MetadataContext, create_dir_all_wc, read_dir_wc, read_to_string_wc,
+        write_wc,
+    },
+    path::PathContext,
+    time::SystemTimeContext,
+};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+
+/// Return whether `root`'s fingerprint matches the one saved by the last successful run of
+/// `command`
+pub fn is_fresh(command: &Command, root: &Path) -> Result<bool> {
+    let path = fingerprint_path(command, root)?;
+    if !path.try_exists_wc()? {
+        return Ok(false);
+    }
+    let saved = read_to_string_wc(&path)?;
+    Ok(saved == compute(command, root)?)
+}
+
+/// Save `root`'s fingerprint after a successful run of `command`
+pub fn save(command: &Command, root: &Path) -> Result<()> {
+    let path = fingerprint_path(command, root)?;
+    create_dir_all_wc(path.parent_wc()?)?;
+    let fingerprint = compute(command, root)?;
+    write_wc(&path, fingerprint)?;
+    Ok(())
+}
+
+// smoelius: A fingerprint is stored in the build script's `OUT_DIR`, one file per root and command
+// line. That way, alternating between, e.g., `cargo check` and `cargo build` does not cause either
+// to be rerun. The file name is a hash of the root's path and the command line, as they could be
+// too long for a file name.
+fn fingerprint_path(command: &Command, root: &Path) -> Result<PathBuf> {
+    let out_dir = var_wc("OUT_DIR")?;
+    let mut hasher = DefaultHasher::new();
+    root.hash(&mut hasher);
+    command_line(command).hash(&mut hasher);
+    Ok(PathBuf::from(out_dir)
+        .join("nested_workspace.fingerprints")
+        .join(format!("{:016x}", hasher.finish())))
+}
+
+/// Compute a fingerprint of `command` and of the files in `root`
+///
+/// The fingerprint is human readable: the command line, followed by one line per file with the
+/// file's path, size, and modification time.
+fn compute(command: &Command, root: &Path) -> Result<String> {
+    let mut lines = Vec::new();
+    collect_files(root, root, &mut lines)?;
+    lines.sort();
+    lines.insert(0, command_line(command));
+    Ok(lines.join("\n") + "\n")
+}
+
+fn collect_files(root: &Path, dir: &Path, lines: &mut Vec<String>) -> Result<()> {
+    for result in read_dir_wc(dir)? {
+        let entry = result?;
+        let path = entry.path();
+        let file_type = entry.file_type_wc()?;
+        if file_type.is_dir() {
+            // smoelius: Skip Git's directory and build directories, which Cargo marks with a
+            // `CACHEDIR.TAG` file.
+            if entry.file_name() == ".git" || path.join("CACHEDIR.TAG").try_exists_wc()? {
+                continue;
+            }
+            collect_files(root, &path, lines)?;
+            continue;
+        }
+        let metadata = entry.metadata_wc()?;
+        let modified = metadata.modified_wc()?.duration_since_wc(UNIX_EPOCH)?;
+        lines.push(format!(
+            "{} {} {}",
+            path.strip_prefix_wc(root)?.display(),
+            metadata.len(),
+            modified.as_nanos()
+        ));
+    }
+    Ok(())
+}
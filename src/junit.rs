@@ -0,0 +1,221 @@
+use crate::util::{StripCurrentDir, command_line};
+use anyhow::Result;
+use elaborate::std::{env::var_wc, fs::write_wc};
+use serde::Deserialize;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::Mutex,
+};
+
+pub const JUNIT_ENV: &str = "NESTED_WORKSPACE_JUNIT";
+
+/// Test suites recorded so far by this process
+///
+/// The report is rewritten after each root so that it is complete even if a later root fails.
+static SUITES: Mutex<Vec<TestSuite>> = Mutex::new(Vec::new());
+
+struct TestSuite {
+    name: String,
+    test_cases: Vec<TestCase>,
+}
+
+struct TestCase {
+    name: String,
+    time: Option<f64>,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    name: Option<String>,
+    stdout: Option<String>,
+    exec_time: Option<f64>,
+}
+
+/// Records the results of `cargo test` run on one root for a JUnit XML report
+///
+/// If the root's tests are run with libtest's JSON output (i.e., with `-- -Z unstable-options
+/// --format json` on nightly), the root contributes one `<testsuite>` per test binary and one
+/// `<testcase>` per test. Otherwise, the root contributes one `<testsuite>` with a single
+/// `<testcase>` reflecting whether the command succeeded.
+pub struct Recorder {
+    path: PathBuf,
+    label: String,
+    suites: Vec<TestSuite>,
+    current: Option<TestSuite>,
+}
+
+impl Recorder {
+    /// Return a recorder for `root` if a report was requested with [`JUNIT_ENV`]
+    pub fn new(root: &Path) -> Option<Self> {
+        let path = var_wc(JUNIT_ENV).ok().filter(|value| !value.is_empty())?;
+        Some(Self {
+            path: PathBuf::from(path),
+            label: root.strip_current_dir().display().to_string(),
+            suites: Vec::new(),
+            current: None,
+        })
+    }
+
+    /// Keep nested tests from writing their own reports to the same file
+    pub fn configure(command: &mut Command) {
+        command.env_remove(JUNIT_ENV);
+    }
+
+    /// Process one line of the root's stdout
+    pub fn observe(&mut self, line: &[u8]) {
+        if !line.trim_ascii_start().starts_with(b"{") {
+            return;
+        }
+        let Ok(message) = serde_json::from_slice::<Message>(line) else {
+            return;
+        };
+        match (message.kind.as_str(), message.event.as_str()) {
+            ("suite", "started") => {
+                self.finish_suite();
+                let name = format!("{} (test binary {})", self.label, self.suites.len() + 1);
+                self.current = Some(TestSuite {
+                    name,
+                    test_cases: Vec::new(),
+                });
+            }
+            ("suite", _) => self.finish_suite(),
+            ("test", "ok" | "failed" | "ignored") => {
+                let Some(suite) = self.current.as_mut() else {
+                    return;
+                };
+                let outcome = match message.event.as_str() {
+                    "ok" => Outcome::Passed,
+                    "failed" => Outcome::Failed(message.stdout.unwrap_or_default()),
+                    _ => Outcome::Skipped,
+                };
+                suite.test_cases.push(TestCase {
+                    name: message.name.unwrap_or_default(),
+                    time: message.exec_time,
+                    outcome,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn finish_suite(&mut self) {
+        if let Some(suite) = self.current.take() {
+            self.suites.push(suite);
+        }
+    }
+
+    /// Add the root's results to the report and write the report
+    ///
+    /// If no libtest JSON was observed, or if the command failed without any test failing (e.g.,
+    /// because of a compilation error), a test case for the command itself is added.
+    pub fn finish(mut self, command: &Command, status: ExitStatus) -> Result<()> {
+        self.finish_suite();
+
+        let any_failed = self.suites.iter().any(|suite| {
+            suite
+                .test_cases
+                .iter()
+                .any(|test_case| matches!(test_case.outcome, Outcome::Failed(_)))
+        });
+        if self.suites.is_empty() || (!status.success() && !any_failed) {
+            let outcome = if status.success() {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(format!("command failed: {command:?}: {status}"))
+            };
+            self.suites.push(TestSuite {
+                name: self.label.clone(),
+                test_cases: vec![TestCase {
+                    name: command_line(command),
+                    time: None,
+                    outcome,
+                }],
+            });
+        }
+
+        let mut suites = SUITES.lock().unwrap();
+        suites.extend(self.suites);
+        write_wc(&self.path, to_xml(&suites))?;
+
+        Ok(())
+    }
+}
+
+fn to_xml(suites: &[TestSuite]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for suite in suites {
+        let failures = suite
+            .test_cases
+            .iter()
+            .filter(|test_case| matches!(test_case.outcome, Outcome::Failed(_)))
+            .count();
+        let skipped = suite
+            .test_cases
+            .iter()
+            .filter(|test_case| matches!(test_case.outcome, Outcome::Skipped))
+            .count();
+        writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\">",
+            escape(&suite.name),
+            suite.test_cases.len(),
+        )
+        .unwrap();
+        for test_case in &suite.test_cases {
+            let time = test_case
+                .time
+                .map_or_else(String::new, |time| format!(" time=\"{time}\""));
+            let open = format!(
+                "    <testcase classname=\"{}\" name=\"{}\"{time}",
+                escape(&suite.name),
+                escape(&test_case.name),
+            );
+            match &test_case.outcome {
+                Outcome::Passed => writeln!(xml, "{open}/>").unwrap(),
+                Outcome::Failed(output) => writeln!(
+                    xml,
+                    "{open}>\n      <failure message=\"failed\">{}</failure>\n    </testcase>",
+                    escape(output)
+                )
+                .unwrap(),
+                Outcome::Skipped => {
+                    writeln!(xml, "{open}>\n      <skipped/>\n    </testcase>").unwrap();
+                }
+            }
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // smoelius: Other control characters (e.g., those in ANSI escape sequences) are not
+            // allowed in XML.
+            '\t' | '\n' | '\r' => escaped.push(c),
+            _ if c.is_control() => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
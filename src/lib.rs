@@ -1,9 +1,9 @@
-use anyhow::{Result, bail, ensure};
-use cargo_metadata::{MetadataCommand, Package};
+use anyhow::{Result, bail};
+use cargo_metadata::{DependencyKind, Package};
 use elaborate::std::{
-    env::var_wc,
+    env::{current_dir_wc, var_wc},
     fs::{FileContext, OpenOptionsContext, write_wc},
-    process::CommandContext,
+    path::PathContext,
 };
 use glob::glob;
 use log::debug;
@@ -14,51 +14,125 @@ use std::{
     fs::OpenOptions,
     io::Write,
     path::{Path, PathBuf},
-    time::SystemTime,
+    process::Command,
+    time::{Duration, SystemTime},
 };
 
 mod cargo_nested;
 
 mod command;
-use command::parent_cargo_command;
+#[cfg(not(feature = "__disable_offline_check"))]
+use command::offline;
 pub use command::{
-    CargoSubcommand, PackageContext, build_cargo_command, parse_cargo_command,
+    CargoCommand, CargoSubcommand, PackageContext, build_cargo_command, parse_cargo_command,
     parse_cargo_subcommand,
 };
+use command::{build_exec_command, parent_cargo_command};
+
+mod dry_run;
+pub use dry_run::DRY_RUN_ENV;
+
+mod error;
+pub use error::Error;
+
+mod fingerprint;
+
+mod junit;
+pub use junit::JUNIT_ENV;
+use junit::Recorder;
+
+mod lock_report;
+use lock_report::LockReportPolicy;
+pub use lock_report::lock_report;
+
+mod merged_metadata;
+pub use merged_metadata::merged_metadata;
+
+mod metadata_cache;
+use metadata_cache::no_deps_metadata;
+
+mod output;
+pub use output::{OUTPUT_ENV, OutputMode};
 
 mod reentrancy_guard;
 use reentrancy_guard::check_reentrancy_guard;
 
+mod sanitize;
+pub use sanitize::EnvPolicy;
+
+mod signal;
+
+mod tests_macro;
+use tests_macro::write_tests;
+
+mod timeout;
+pub use timeout::{TIMEOUT_ENV, parse_duration};
+
+mod tree;
+pub use tree::{NestedWorkspaceNode, NestedWorkspaceTree};
+
 mod util;
-use util::{Delimiter, StripCurrentDir};
+#[cfg(not(feature = "__disable_offline_check"))]
+use util::StripCurrentDir;
+
+mod verify;
+pub use verify::verify;
 
 #[derive(Deserialize)]
 struct Metadata {
     roots: Vec<MetadataRoot>,
+    #[serde(default)]
+    env: EnvPolicy,
+    #[serde(default)]
+    fingerprint: bool,
+    /// Whether a root that matches nothing is an error rather than a warning
+    #[serde(default)]
+    strict: bool,
+    lock_report: Option<LockReportPolicy>,
 }
 
 #[derive(Deserialize)]
 #[serde(untagged)]
 enum MetadataRoot {
     Path(String),
-    PathWithDependent {
+    PathWithOptions {
         path: String,
         #[serde(default)]
         dependent: bool,
+        #[serde(default)]
+        optional: bool,
+        timeout: Option<String>,
     },
 }
 
 impl MetadataRoot {
     fn path(&self) -> &str {
         match self {
-            Self::Path(path) | Self::PathWithDependent { path, .. } => path,
+            Self::Path(path) | Self::PathWithOptions { path, .. } => path,
         }
     }
 
     fn dependent(&self) -> bool {
         match self {
             Self::Path(_) => false,
-            Self::PathWithDependent { dependent, .. } => *dependent,
+            Self::PathWithOptions { dependent, .. } => *dependent,
+        }
+    }
+
+    fn optional(&self) -> bool {
+        match self {
+            Self::Path(_) => false,
+            Self::PathWithOptions { optional, .. } => *optional,
+        }
+    }
+
+    fn timeout(&self) -> Result<Option<Duration>> {
+        match self {
+            Self::Path(_) | Self::PathWithOptions { timeout: None, .. } => Ok(None),
+            Self::PathWithOptions {
+                timeout: Some(timeout),
+                ..
+            } => parse_duration(timeout).map(Some),
         }
     }
 }
@@ -66,6 +140,10 @@ impl MetadataRoot {
 pub struct NestedWorkspaceRoot {
     path: PathBuf,
     package: PackageContext,
+    manifest_path: PathBuf,
+    pattern: String,
+    timeout: Option<Duration>,
+    fingerprint: bool,
 }
 
 impl NestedWorkspaceRoot {
@@ -78,6 +156,31 @@ impl NestedWorkspaceRoot {
     pub fn dependent(&self) -> bool {
         self.package.dependent
     }
+
+    /// Return the name of the package whose `nested_workspace` metadata lists the root
+    #[must_use]
+    pub fn package_name(&self) -> &str {
+        &self.package.name
+    }
+
+    /// Return the path of the manifest of the package whose `nested_workspace` metadata lists the
+    /// root
+    #[must_use]
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// Return the pattern in the `roots` array that matched the root
+    #[must_use]
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Return the timeout set in the nested workspace metadata, if any
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -102,6 +205,10 @@ pub fn build() -> Builder {
     Builder {
         source: Source::BuildScript,
         args: Vec::new(),
+        roots: Vec::new(),
+        exclude_roots: Vec::new(),
+        filters: Vec::new(),
+        tests: false,
     }
 }
 
@@ -110,14 +217,24 @@ pub fn test() -> Builder {
     Builder {
         source: Source::Test,
         args: Vec::new(),
+        roots: Vec::new(),
+        exclude_roots: Vec::new(),
+        filters: Vec::new(),
+        tests: false,
     }
 }
 
 pub struct Builder {
     source: Source,
     args: Vec<OsString>,
+    roots: Vec<PathBuf>,
+    exclude_roots: Vec<PathBuf>,
+    filters: Vec<RootFilter>,
+    tests: bool,
 }
 
+type RootFilter = Box<dyn Fn(&NestedWorkspaceRoot) -> bool>;
+
 impl Builder {
     /// Pass `arg` to subcommand
     #[must_use]
@@ -141,10 +258,106 @@ impl Builder {
         self
     }
 
+    /// Run only on the nested workspace root at `path`
+    ///
+    /// `path` is relative to the containing package's directory. If called more than once, each
+    /// of the named roots is run on.
+    #[must_use]
+    pub fn root<P>(mut self, path: P) -> Builder
+    where
+        P: AsRef<Path>,
+    {
+        self.roots.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Do not run on the nested workspace root at `path`
+    ///
+    /// `path` is relative to the containing package's directory. If called more than once, none
+    /// of the named roots is run on.
+    #[must_use]
+    pub fn exclude_root<P>(mut self, path: P) -> Builder
+    where
+        P: AsRef<Path>,
+    {
+        self.exclude_roots.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Run only on the nested workspace roots for which `predicate` returns true
+    ///
+    /// If called more than once, only the roots for which every predicate returns true are run
+    /// on. For example, the following runs only on roots whose paths end with `_small`:
+    ///
+    /// ```no_run
+    /// nested_workspace::test()
+    ///     .filter(|root| root.path().to_string_lossy().ends_with("_small"))
+    ///     .unwrap();
+    /// ```
+    #[must_use]
+    pub fn filter<F>(mut self, predicate: F) -> Builder
+    where
+        F: Fn(&NestedWorkspaceRoot) -> bool + 'static,
+    {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Generate the tests that [`tests!`] expands to
+    ///
+    /// Generating the tests requires discovering the nested workspaces, even when the build script
+    /// would otherwise do nothing (e.g., under `cargo nested`). Hence, it is done only if
+    /// requested. Has no effect outside of a build script.
+    #[must_use]
+    pub fn tests(mut self) -> Builder {
+        self.tests = true;
+        self
+    }
+
+    /// Run the parent Cargo command on the nested workspaces, panicking on failure
     pub fn unwrap(self) {
+        self.run_impl().unwrap();
+    }
+
+    /// Run the parent Cargo command on the nested workspaces
+    ///
+    /// Returns which nested workspaces the command was run on.
+    pub fn run(self) -> std::result::Result<RunReport, Error> {
+        self.run_impl().map_err(Error::from)
+    }
+
+    /// Like [`Builder::run`], but on failure, print the error and exit with a non-zero status
+    /// rather than return it
+    ///
+    /// In a build script, the error is printed as `cargo::error` directives, so that Cargo shows
+    /// it without a panic message or backtrace hint.
+    // smoelius: Callers typically ignore the report, e.g., `nested_workspace::build().run_or_exit();`.
+    #[expect(clippy::must_use_candidate)]
+    pub fn run_or_exit(self) -> RunReport {
+        let source = self.source;
+        match self.run_impl() {
+            Ok(report) => report,
+            Err(error) => {
+                if matches!(source, Source::BuildScript) {
+                    for line in format!("{error:#}").lines() {
+                        println!("cargo::error={line}");
+                    }
+                } else {
+                    eprintln!("Error: {error:?}");
+                }
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn run_impl(self) -> Result<RunReport> {
+        let mut roots = None;
+
         if matches!(self.source, Source::BuildScript) {
-            if check_reentrancy_guard().unwrap() {
-                return;
+            util::set_build_script();
+
+            if check_reentrancy_guard()? {
+                return Ok(RunReport::default());
             }
 
             // smoelius: Suppose a user runs `cargo check` followed by `cargo build`. Cargo's
@@ -152,32 +365,46 @@ impl Builder {
             // but not again for the second. However, we need to the build script to be rerun so
             // that we can call `cargo build` for the nested workspaces. `force_rerun` is a hack
             // to achieve this.
-            force_rerun().unwrap();
+            force_rerun()?;
+
+            // smoelius: Generate the tests for `tests!` before possibly returning below.
+            // Otherwise, the tests could not be compiled under `cargo nested`.
+            if self.tests {
+                let result = current_package_nested_workspace_roots();
+                write_tests(result.as_deref())?;
+                roots = Some(result);
+            }
         }
 
         // `cargo nested` traverses nested workspaces itself. Do not also traverse them through a
         // containing package's build script or test, as that would run commands more than once.
         if cargo_nested::enabled() {
-            return;
+            return Ok(RunReport::default());
         }
 
-        self.run_parent_cargo_command_on_current_package_nested_workspace_roots()
-            .unwrap();
+        self.run_parent_cargo_command_on_current_package_nested_workspace_roots(roots)
     }
 
-    fn run_parent_cargo_command_on_current_package_nested_workspace_roots(self) -> Result<()> {
+    fn run_parent_cargo_command_on_current_package_nested_workspace_roots(
+        self,
+        roots: Option<Result<Vec<NestedWorkspaceRoot>>>,
+    ) -> Result<RunReport> {
         let (subcommand, subcommand_args) = parent_cargo_command()?;
 
         let mut args = self.args;
         args.extend(subcommand_args.iter().map(OsString::from));
 
-        let roots = current_package_nested_workspace_roots()?;
+        let roots = roots.unwrap_or_else(current_package_nested_workspace_roots)?;
+        let roots = select_roots(&self.roots, &self.exclude_roots, roots)?
+            .into_iter()
+            .filter(|root| self.filters.iter().all(|predicate| predicate(root)))
+            .collect::<Vec<_>>();
 
         #[cfg(not(feature = "__disable_offline_check"))]
-        if matches!(subcommand, CargoSubcommand::Build | CargoSubcommand::Check)
-            && !subcommand_args
-                .iter()
-                .any(|arg| arg == "--frozen" || arg == "--offline")
+        if matches!(
+            subcommand,
+            CargoSubcommand::Build | CargoSubcommand::Check | CargoSubcommand::Clippy
+        ) && !offline(&subcommand_args)
         {
             println!(
                 "cargo::warning=Since `--offline` was not passed to parent command, refusing to \
@@ -189,19 +416,90 @@ impl Builder {
                     root.path.as_path().strip_current_dir().display()
                 );
             }
-            return Ok(());
+            return Ok(RunReport {
+                refused: roots.into_iter().map(|root| root.path).collect(),
+                ..RunReport::default()
+            });
         }
 
-        run_cargo_subcommand_on_nested_workspace_roots(
+        run_task_on_nested_workspace_roots(
             self.source,
-            &subcommand,
-            &args,
+            &Task::Cargo(&subcommand, &args),
             None,
             &roots,
             false,
-        )?;
-        Ok(())
+        )
+    }
+}
+
+/// Which nested workspaces [`Builder::run`] ran the parent Cargo command on
+#[derive(Debug, Default)]
+pub struct RunReport {
+    ran: Vec<PathBuf>,
+    unchanged: Vec<PathBuf>,
+    refused: Vec<PathBuf>,
+}
+
+impl RunReport {
+    /// Return the roots of the nested workspaces the command was run on
+    #[must_use]
+    pub fn ran(&self) -> &[PathBuf] {
+        &self.ran
+    }
+
+    /// Return the roots of the nested workspaces skipped because they were unchanged (see
+    /// `fingerprint` in the README)
+    #[must_use]
+    pub fn unchanged(&self) -> &[PathBuf] {
+        &self.unchanged
+    }
+
+    /// Return the roots of the nested workspaces the command was not run on because `--offline`
+    /// was not passed to the parent command
+    #[must_use]
+    pub fn refused(&self) -> &[PathBuf] {
+        &self.refused
+    }
+}
+
+/// Keep the roots named by `paths` (or all roots if `paths` is empty), except those named by
+/// `exclude_paths`
+fn select_roots(
+    paths: &[PathBuf],
+    exclude_paths: &[PathBuf],
+    roots: Vec<NestedWorkspaceRoot>,
+) -> Result<Vec<NestedWorkspaceRoot>> {
+    if paths.is_empty() && exclude_paths.is_empty() {
+        return Ok(roots);
+    }
+    let cargo_manifest_dir = var_wc("CARGO_MANIFEST_DIR")?;
+    let roots_canonical = roots
+        .iter()
+        .map(|root| dunce::canonicalize(&root.path))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let position = |path: &PathBuf| -> Result<usize> {
+        let path_canonical = dunce::canonicalize(Path::new(&cargo_manifest_dir).join(path))
+            .unwrap_or_else(|_| path.clone());
+        let Some(index) = roots_canonical
+            .iter()
+            .position(|root_canonical| *root_canonical == path_canonical)
+        else {
+            return Err(Error::UnknownRoot { path: path.clone() }.into());
+        };
+        Ok(index)
+    };
+    let mut selected = vec![paths.is_empty(); roots.len()];
+    for path in paths {
+        selected[position(path)?] = true;
+    }
+    for path in exclude_paths {
+        selected[position(path)?] = false;
     }
+    Ok(roots
+        .into_iter()
+        .zip(selected)
+        .filter_map(|(root, selected)| selected.then_some(root))
+        .collect())
 }
 
 const TIMESTAMP_CONTENTS: &str =
@@ -229,17 +527,96 @@ fn touch(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// What is run on the current package or workspace and on nested workspace roots
+enum Task<'a, T> {
+    /// A Cargo subcommand and its arguments
+    Cargo(&'a CargoSubcommand, &'a [T]),
+    /// A program and its arguments, as with `cargo nested exec`
+    Exec(&'a [T]),
+}
+
+impl<T: AsRef<OsStr> + Debug> Task<'_, T> {
+    fn build_command(
+        &self,
+        source: Source,
+        package: Option<&PackageContext>,
+        dir: &Path,
+    ) -> Result<Command> {
+        match self {
+            Task::Cargo(subcommand, args) => build_cargo_command(source, package, subcommand, args),
+            Task::Exec(program_and_args) => build_exec_command(package, dir, program_and_args),
+        }
+    }
+}
+
+/// Run `subcommand` with `args` on the current package or workspace
+///
+/// `cargo nested` calls this function before running the subcommand on the nested workspaces.
+pub fn run_cargo_subcommand_on_current_workspace<T: AsRef<OsStr> + Debug>(
+    subcommand: &CargoSubcommand,
+    args: &[T],
+) -> Result<()> {
+    run_task_on_current_workspace(&Task::Cargo(subcommand, args))
+}
+
+/// Run the program named by the first element of `program_and_args` on the current package or
+/// workspace
+///
+/// `cargo nested exec` calls this function before running the program on the nested workspaces.
+pub fn run_program_on_current_workspace<T: AsRef<OsStr> + Debug>(
+    program_and_args: &[T],
+) -> Result<()> {
+    run_task_on_current_workspace(&Task::Exec(program_and_args))
+}
+
+fn run_task_on_current_workspace<T: AsRef<OsStr> + Debug>(task: &Task<T>) -> Result<()> {
+    let current_dir = current_dir_wc()?;
+    let mut command = task.build_command(Source::CargoNested, None, &current_dir)?;
+    if dry_run::enabled() {
+        return dry_run::print_command(&command, &current_dir);
+    }
+    let status = output::status_inherited(&mut command)?;
+    if !status.success() {
+        return Err(Error::CommandFailed {
+            command: format!("{command:?}"),
+            status,
+        }
+        .into());
+    }
+    Ok(())
+}
+
 pub fn run_cargo_subcommand_on_all_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
     subcommand: &CargoSubcommand,
     args: &[T],
     dir: &Path,
     is_recursive_call: bool,
+) -> Result<()> {
+    run_task_on_all_nested_workspace_roots(&Task::Cargo(subcommand, args), dir, is_recursive_call)
+}
+
+/// Run the program named by the first element of `program_and_args` on each nested workspace
+/// root in `dir`
+///
+/// The program is passed the root's path in `NESTED_WORKSPACE_ROOT` and the containing package's
+/// name in `NESTED_WORKSPACE_PACKAGE`.
+pub fn run_program_on_all_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
+    program_and_args: &[T],
+    dir: &Path,
+    is_recursive_call: bool,
+) -> Result<()> {
+    run_task_on_all_nested_workspace_roots(&Task::Exec(program_and_args), dir, is_recursive_call)
+}
+
+fn run_task_on_all_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
+    task: &Task<T>,
+    dir: &Path,
+    is_recursive_call: bool,
 ) -> Result<()> {
     let roots = all_nested_workspace_roots(dir)?;
-    run_cargo_subcommand_on_nested_workspace_roots(
+    run_task_on_nested_workspace_roots(
         Source::CargoNested,
-        subcommand,
-        args,
+        task,
         Some(dir),
         &roots,
         is_recursive_call,
@@ -247,14 +624,13 @@ pub fn run_cargo_subcommand_on_all_nested_workspace_roots<T: AsRef<OsStr> + Debu
     Ok(())
 }
 
-fn run_cargo_subcommand_on_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
+fn run_task_on_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
     source: Source,
-    subcommand: &CargoSubcommand,
-    args: &[T],
+    task: &Task<T>,
     dir: Option<&Path>,
     roots: &[NestedWorkspaceRoot],
     is_recursive_call: bool,
-) -> Result<()> {
+) -> Result<RunReport> {
     env_logger::try_init().unwrap_or_default();
     if roots.is_empty() {
         if !is_recursive_call {
@@ -264,27 +640,99 @@ fn run_cargo_subcommand_on_nested_workspace_roots<T: AsRef<OsStr> + Debug>(
                 "Warning: found no nested workspaces{in_dir}",
             )?;
         }
-        return Ok(());
+        return Ok(RunReport::default());
     }
+    // smoelius: A build script or test run on a root that depends on the containing package would
+    // reenter the containing package's build script, which would fail with a less helpful "cycle
+    // detected" error. `cargo nested` disables build scripts' and tests' traversal, so it is not
+    // affected.
+    if !matches!(source, Source::CargoNested) {
+        for root in roots {
+            check_dependent(source, root)?;
+        }
+    }
+    let mut report = RunReport::default();
     for root in roots {
-        let _delimiter = Delimiter::new(&root.path);
-        let mut command = build_cargo_command(source, Some(&root.package), subcommand, args)?;
+        let mut command = task.build_command(source, Some(&root.package), &root.path)?;
         command.current_dir(&root.path);
-        debug!("{source}: {command:?}");
-        let status = command.status_wc()?;
-        ensure!(status.success(), "command failed: {command:?}");
+        let fingerprint = uses_fingerprint(source, task, root);
+        if dry_run::enabled() {
+            dry_run::print_command(&command, &root.path)?;
+        } else if fingerprint && fingerprint::is_fresh(&command, &root.path)? {
+            debug!("skipping `{}` as it is unchanged", root.path.display());
+            report.unchanged.push(root.path.clone());
+        } else {
+            run_command_on_root(source, task, &mut command, root)?;
+            if fingerprint {
+                fingerprint::save(&command, &root.path)?;
+            }
+            report.ran.push(root.path.clone());
+        }
         // smoelius: `cargo nested` is a special case. It must be run manually on each nested
         // workspace root to ensure that _nested_-nested workspaces are handled.
         if matches!(source, Source::CargoNested) {
-            run_cargo_subcommand_on_all_nested_workspace_roots(subcommand, args, &root.path, true)?;
+            run_task_on_all_nested_workspace_roots(task, &root.path, true)?;
         }
     }
+    Ok(report)
+}
+
+// smoelius: Fingerprints are used only by build scripts, as tests should always be run. Moreover,
+// they are not used for dependent roots, as a dependent root's build depends on files outside of
+// the root.
+fn uses_fingerprint<T>(source: Source, task: &Task<T>, root: &NestedWorkspaceRoot) -> bool {
+    matches!(source, Source::BuildScript)
+        && matches!(task, Task::Cargo(..))
+        && root.fingerprint
+        && !root.dependent()
+}
+
+fn run_command_on_root<T>(
+    source: Source,
+    task: &Task<T>,
+    command: &mut Command,
+    root: &NestedWorkspaceRoot,
+) -> Result<()> {
+    // smoelius: JUnit reports are generated for `cargo test` and `cargo nested test`.
+    let mut recorder = if matches!(
+        task,
+        Task::Cargo(CargoSubcommand::Test | CargoSubcommand::Nextest, _)
+    ) && !matches!(source, Source::BuildScript)
+    {
+        Recorder::new(&root.path)
+    } else {
+        None
+    };
+    if recorder.is_some() {
+        Recorder::configure(command);
+    }
+    let timeout = timeout::from_env()?.or(root.timeout);
+    debug!("{source}: {command:?}");
+    let status = match recorder.as_mut() {
+        Some(recorder) => output::status(
+            command,
+            &root.path,
+            Some(&mut |line: &[u8]| recorder.observe(line)),
+            timeout,
+        )?,
+        None => output::status(command, &root.path, None, timeout)?,
+    };
+    if let Some(recorder) = recorder {
+        recorder.finish(command, status)?;
+    }
+    if !status.success() {
+        return Err(Error::CommandFailed {
+            command: format!("{command:?}"),
+            status,
+        }
+        .into());
+    }
     Ok(())
 }
 
 fn current_package_nested_workspace_roots() -> Result<Vec<NestedWorkspaceRoot>> {
     let cargo_manifest_path = var_wc("CARGO_MANIFEST_PATH")?;
-    let cargo_metadata = MetadataCommand::new().no_deps().exec()?;
+    let cargo_metadata = no_deps_metadata(&current_dir_wc()?)?;
     let Some(package) = cargo_metadata
         .packages
         .iter()
@@ -293,14 +741,23 @@ fn current_package_nested_workspace_roots() -> Result<Vec<NestedWorkspaceRoot>>
         bail!("failed to find package with manifest at `{cargo_manifest_path}`");
     };
     let Some(roots) = nested_workspace_roots_for_package(package)? else {
-        bail!("package at `{cargo_manifest_path}` has no `nested_workspace` metadata");
+        return Err(Error::NoMetadata {
+            manifest_path: cargo_manifest_path.into(),
+        }
+        .into());
     };
     Ok(roots)
 }
 
 pub fn all_nested_workspace_roots(dir: &Path) -> Result<Vec<NestedWorkspaceRoot>> {
+    let cargo_metadata = no_deps_metadata(dir)?;
+    nested_workspace_roots_for_workspace(&cargo_metadata)
+}
+
+fn nested_workspace_roots_for_workspace(
+    cargo_metadata: &cargo_metadata::Metadata,
+) -> Result<Vec<NestedWorkspaceRoot>> {
     let mut roots = Vec::new();
-    let cargo_metadata = MetadataCommand::new().current_dir(dir).no_deps().exec()?;
     for package in &cargo_metadata.packages {
         if let Some(current_roots) = nested_workspace_roots_for_package(package)? {
             roots.extend(current_roots);
@@ -309,9 +766,7 @@ pub fn all_nested_workspace_roots(dir: &Path) -> Result<Vec<NestedWorkspaceRoot>
     Ok(roots)
 }
 
-fn nested_workspace_roots_for_package(
-    package: &Package,
-) -> Result<Option<Vec<NestedWorkspaceRoot>>> {
+fn nested_workspace_metadata(package: &Package) -> Result<Option<Metadata>> {
     let Some(nested_workspace_value) = package
         .metadata
         .as_object()
@@ -319,18 +774,29 @@ fn nested_workspace_roots_for_package(
     else {
         return Ok(None);
     };
+    let nested_workspace_metadata =
+        serde_json::from_value::<Metadata>(nested_workspace_value.clone())?;
+    Ok(Some(nested_workspace_metadata))
+}
+
+fn nested_workspace_roots_for_package(
+    package: &Package,
+) -> Result<Option<Vec<NestedWorkspaceRoot>>> {
+    let Some(nested_workspace_metadata) = nested_workspace_metadata(package)? else {
+        return Ok(None);
+    };
     let Some(cargo_manifest_dir) = package.manifest_path.parent() else {
         bail!(
             "failed to get manifest dir from `{}`",
             package.manifest_path
         );
     };
-    let nested_workspace_metadata =
-        serde_json::from_value::<Metadata>(nested_workspace_value.clone())?;
     let mut roots = Vec::new();
     for root in nested_workspace_metadata.roots {
+        let mut matched = false;
         for result in glob(&format!("{cargo_manifest_dir}/{}", root.path()))? {
             let path = result?;
+            matched = true;
             if !validate_root(&path)? {
                 writeln!(
                     std::io::stderr(),
@@ -344,16 +810,73 @@ fn nested_workspace_roots_for_package(
                 package: PackageContext {
                     name: package.name.to_string(),
                     dependent: root.dependent(),
+                    env: nested_workspace_metadata.env.clone(),
                 },
+                manifest_path: package.manifest_path.clone().into_std_path_buf(),
+                pattern: root.path().to_owned(),
+                timeout: root.timeout()?,
+                fingerprint: nested_workspace_metadata.fingerprint,
             });
         }
+        if !matched && !root.optional() {
+            let error = Error::UnmatchedRoot {
+                manifest_path: package.manifest_path.clone().into_std_path_buf(),
+                pattern: root.path().to_owned(),
+            };
+            if nested_workspace_metadata.strict {
+                return Err(error.into());
+            }
+            util::warn(&error.to_string())?;
+        }
     }
     Ok(Some(roots))
 }
 
+/// Verify that `root` is marked `dependent` if it depends on its containing package
+///
+/// Dev-dependencies are considered only when `source` is [`Source::Test`], as they are not compiled
+/// by the commands a build script runs.
+fn check_dependent(source: Source, root: &NestedWorkspaceRoot) -> Result<()> {
+    if root.dependent() {
+        return Ok(());
+    }
+    let cargo_metadata = no_deps_metadata(&root.path)?;
+    let manifest_dir = root.manifest_path.parent_wc()?;
+    let dev = matches!(source, Source::Test);
+    if depends_on(&cargo_metadata, manifest_dir, dev)? {
+        return Err(Error::UndeclaredDependent {
+            package: root.package.name.clone(),
+            root: root.path.clone(),
+            pattern: root.pattern.clone(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Return whether any package in the workspace described by `cargo_metadata` has a path
+/// dependency on the package in `manifest_dir`
+///
+/// Dev-dependencies are considered only if `dev` is true.
+fn depends_on(
+    cargo_metadata: &cargo_metadata::Metadata,
+    manifest_dir: &Path,
+    dev: bool,
+) -> Result<bool> {
+    let manifest_dir_canonical = dunce::canonicalize(manifest_dir)?;
+    Ok(cargo_metadata.packages.iter().any(|package| {
+        package.dependencies.iter().any(|dependency| {
+            (dev || dependency.kind != DependencyKind::Development)
+                && dependency.path.as_ref().is_some_and(|path| {
+                    dunce::canonicalize(path).is_ok_and(|path| path == manifest_dir_canonical)
+                })
+        })
+    }))
+}
+
 /// Run `cargo metadata` in `root` and verify there is a workspace rooted there.
 fn validate_root(root: &Path) -> Result<bool> {
-    let cargo_metadata = MetadataCommand::new().current_dir(root).no_deps().exec()?;
+    let cargo_metadata = no_deps_metadata(root)?;
     let root_canonical = dunce::canonicalize(root)?;
     Ok(root_canonical == cargo_metadata.workspace_root)
 }
@@ -0,0 +1,174 @@
+use crate::{NestedWorkspaceTree, nested_workspace_metadata, util::StripCurrentDir};
+use anyhow::{Result, bail};
+use elaborate::std::{fs::read_to_string_wc, path::PathContext};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Write,
+    path::Path,
+};
+
+/// Limits on crates resolved to different versions across workspaces
+///
+/// Set in the `lock_report` table of the containing package's `nested_workspace` metadata.
+#[derive(Deserialize)]
+pub struct LockReportPolicy {
+    /// Maximum number of crates that may be resolved to different versions
+    max_duplicates: Option<usize>,
+    /// Crates to leave out of the report
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+/// A crate in a `Cargo.lock` file
+struct LockedPackage {
+    name: String,
+    version: String,
+}
+
+/// Report crates that are resolved to different versions in the `Cargo.lock` files of the
+/// workspace containing `dir` and of all nested workspaces, recursively
+///
+/// Versions are compared only if they are semver compatible. For example, if one workspace uses
+/// `syn` 1.0.109 and another uses `syn` 2.0.100, the two are not reported.
+///
+/// Fails if the `lock_report` table of the `nested_workspace` metadata sets `max_duplicates` and
+/// more crates than that are reported.
+pub fn lock_report(dir: &Path) -> Result<()> {
+    let tree = NestedWorkspaceTree::discover_with_metadata(dir)?;
+
+    let mut policy = None;
+    for package in tree
+        .metadata()
+        .map_or(&[][..], |metadata| &metadata.packages)
+    {
+        if let Some(metadata) = nested_workspace_metadata(package)?
+            && metadata.lock_report.is_some()
+        {
+            policy = metadata.lock_report;
+            break;
+        }
+    }
+
+    let workspace_roots = std::iter::once(tree.root())
+        .chain(tree.nodes().into_iter().map(|(_, node)| node.root().path()))
+        .collect::<Vec<_>>();
+
+    // smoelius: Map each crate name and semver-compatibility key to the versions it is resolved to,
+    // and each version to the workspaces in which it is used.
+    let mut versions = BTreeMap::<(String, String), BTreeMap<String, BTreeSet<String>>>::new();
+    for workspace_root in workspace_roots {
+        let label = label(workspace_root);
+        let lockfile = workspace_root.join("Cargo.lock");
+        if !lockfile.try_exists_wc()? {
+            writeln!(
+                std::io::stderr(),
+                "Warning: skipping `{label}` as it does not contain a Cargo.lock file"
+            )?;
+            continue;
+        }
+        for LockedPackage { name, version } in parse_lockfile(&read_to_string_wc(&lockfile)?) {
+            if policy
+                .as_ref()
+                .is_some_and(|policy| policy.ignore.contains(&name))
+            {
+                continue;
+            }
+            let key = compatibility_key(&version);
+            versions
+                .entry((name, key))
+                .or_default()
+                .entry(version)
+                .or_default()
+                .insert(label.clone());
+        }
+    }
+
+    let mut n_duplicates = 0;
+    let mut stdout = std::io::stdout().lock();
+    for ((name, _), versions) in &versions {
+        if versions.len() <= 1 {
+            continue;
+        }
+        n_duplicates += 1;
+        writeln!(stdout, "{name}")?;
+        for (version, labels) in versions {
+            let labels = labels
+                .iter()
+                .map(|label| format!("`{label}`"))
+                .collect::<Vec<_>>();
+            writeln!(stdout, "    {version}: {}", labels.join(", "))?;
+        }
+    }
+
+    if n_duplicates == 0 {
+        writeln!(
+            stdout,
+            "No crates are resolved to different versions across workspaces"
+        )?;
+    }
+
+    if let Some(max_duplicates) = policy.and_then(|policy| policy.max_duplicates)
+        && n_duplicates > max_duplicates
+    {
+        bail!(
+            "found {n_duplicates} crates resolved to different versions across workspaces, but \
+             `max_duplicates` is {max_duplicates}"
+        );
+    }
+
+    Ok(())
+}
+
+fn label(workspace_root: &Path) -> String {
+    let path = workspace_root.strip_current_dir();
+    if path.as_os_str().is_empty() {
+        String::from(".")
+    } else {
+        path.display().to_string()
+    }
+}
+
+// smoelius: `Cargo.lock` files are generated by Cargo and have a simple, line-oriented structure.
+// Parsing them by hand avoids adding a TOML parser to this crate's dependencies.
+fn parse_lockfile(contents: &str) -> Vec<LockedPackage> {
+    let mut packages = Vec::new();
+    let mut fields = HashMap::new();
+    let mut in_package = false;
+    let mut flush = |fields: &mut HashMap<&str, &str>| {
+        // smoelius: Packages without a `source` are members of the workspace itself.
+        if fields.contains_key("source")
+            && let (Some(name), Some(version)) = (fields.get("name"), fields.get("version"))
+        {
+            packages.push(LockedPackage {
+                name: (*name).to_owned(),
+                version: (*version).to_owned(),
+            });
+        }
+        fields.clear();
+    };
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            flush(&mut fields);
+            in_package = line == "[[package]]";
+            continue;
+        }
+        if in_package && let Some((key, value)) = line.split_once('=') {
+            fields.insert(key.trim(), value.trim().trim_matches('"'));
+        }
+    }
+    flush(&mut fields);
+    packages
+}
+
+/// Return the part of `version` that must match for two versions to be semver compatible, e.g.,
+/// `1` for `1.0.219` and `0.3` for `0.3.31`
+fn compatibility_key(version: &str) -> String {
+    let version = version.split(['-', '+']).next().unwrap_or(version);
+    let parts = version.split('.').collect::<Vec<_>>();
+    let n = parts
+        .iter()
+        .position(|part| *part != "0")
+        .map_or(parts.len(), |i| i + 1);
+    parts[..n].join(".")
+}
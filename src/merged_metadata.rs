@@ -0,0 +1,66 @@
+use crate::NestedWorkspaceTree;
+use anyhow::Result;
+use cargo_metadata::{Metadata, MetadataCommand};
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The `cargo metadata` output of a workspace and all nested workspaces
+#[derive(Serialize)]
+struct MergedMetadata {
+    workspaces: Vec<WorkspaceMetadata>,
+}
+
+/// The `cargo metadata` output of one workspace, annotated with where the workspace was found
+#[derive(Serialize)]
+struct WorkspaceMetadata {
+    /// The workspace's root directory
+    root: PathBuf,
+    /// The name of the package whose `nested_workspace` metadata lists the workspace, or `None` for
+    /// the top-level workspace
+    package: Option<String>,
+    /// 0 for the top-level workspace, 1 for a workspace nested within it, etc.
+    depth: usize,
+    dependent: bool,
+    metadata: Metadata,
+}
+
+/// Print one JSON document containing the `cargo metadata` output of the workspace containing
+/// `dir` and of all nested workspaces, recursively
+///
+/// `args` are passed to each `cargo metadata` command, e.g., `--no-deps` or `--offline`.
+pub fn merged_metadata(dir: &Path, args: &[String]) -> Result<()> {
+    let tree = NestedWorkspaceTree::discover(dir)?;
+
+    let mut workspaces = vec![WorkspaceMetadata {
+        root: tree.root().to_path_buf(),
+        package: None,
+        depth: 0,
+        dependent: false,
+        metadata: metadata_command(tree.root(), args).exec()?,
+    }];
+    for (depth, node) in tree.nodes() {
+        let root = node.root();
+        workspaces.push(WorkspaceMetadata {
+            root: root.path().to_path_buf(),
+            package: Some(root.package_name().to_owned()),
+            depth,
+            dependent: root.dependent(),
+            metadata: metadata_command(root.path(), args).exec()?,
+        });
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &MergedMetadata { workspaces })?;
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+fn metadata_command(dir: &Path, args: &[String]) -> MetadataCommand {
+    let mut command = MetadataCommand::new();
+    command.current_dir(dir).other_options(args.to_vec());
+    command
+}
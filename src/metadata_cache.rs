@@ -0,0 +1,28 @@
+use anyhow::Result;
+use cargo_metadata::{Metadata, MetadataCommand};
+use log::debug;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// smoelius: Discovery needs the metadata of the directory it starts in, of each root (to validate
+// it), and then of each root again (to find the next layer of roots). Memoizing the metadata means
+// `cargo metadata` is run only once per directory.
+static CACHE: Mutex<BTreeMap<PathBuf, Metadata>> = Mutex::new(BTreeMap::new());
+
+/// Return the output of `cargo metadata --no-deps` run in `dir`, running it only if it has not
+/// already been run in `dir` by this process
+pub fn no_deps_metadata(dir: &Path) -> Result<Metadata> {
+    env_logger::try_init().unwrap_or_default();
+    let dir = dunce::canonicalize(dir)?;
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(metadata) = cache.get(&dir) {
+        return Ok(metadata.clone());
+    }
+    debug!("running `cargo metadata --no-deps` in `{}`", dir.display());
+    let metadata = MetadataCommand::new().current_dir(&dir).no_deps().exec()?;
+    cache.insert(dir, metadata.clone());
+    Ok(metadata)
+}
@@ -0,0 +1,193 @@
+use crate::{
+    signal, timeout,
+    util::{Delimiter, StripCurrentDir},
+};
+use anstyle::Style;
+use anyhow::{Result, anyhow, bail};
+use elaborate::std::{env::var_wc, io::WriteContext, process::CommandContext};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, IsTerminal, Read, Write},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+pub const OUTPUT_ENV: &str = "NESTED_WORKSPACE_OUTPUT";
+
+/// Number of lines of output included in the error of a command that timed out
+const TAIL_LEN: usize = 20;
+
+/// How the output of commands run on nested workspace roots is shown
+#[derive(Clone, Copy, Default)]
+pub enum OutputMode {
+    /// Bracket each root's output with `<<<` and `>>>` lines
+    #[default]
+    Delimited,
+    /// Prefix each line of each root's output with the root's path
+    Prefixed,
+}
+
+impl OutputMode {
+    /// Get the output mode selected by [`OUTPUT_ENV`]
+    pub fn from_env() -> Result<Self> {
+        let Ok(value) = var_wc(OUTPUT_ENV) else {
+            return Ok(Self::default());
+        };
+        value.parse()
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "delimited" => Ok(Self::Delimited),
+            "prefixed" => Ok(Self::Prefixed),
+            _ => bail!("unknown output mode `{s}`; expected `delimited` or `prefixed`"),
+        }
+    }
+}
+
+/// A function that is passed each line of a command's stdout
+pub type Observer<'a> = &'a mut (dyn FnMut(&[u8]) + Send);
+
+/// Run `command` on `root`, showing its output as selected by [`OUTPUT_ENV`]
+///
+/// If `observer` is provided, it is passed each line of the command's stdout.
+///
+/// If `timeout` is provided and the command runs longer, the command and its descendants are killed,
+/// and an error containing the last lines of the command's output is returned.
+pub fn status(
+    command: &mut Command,
+    root: &Path,
+    observer: Option<Observer>,
+    timeout: Option<Duration>,
+) -> Result<ExitStatus> {
+    let (_delimiter, label) = match OutputMode::from_env()? {
+        OutputMode::Delimited => (Some(Delimiter::new(root)), None),
+        OutputMode::Prefixed => (None, Some(root.strip_current_dir().display().to_string())),
+    };
+
+    if label.is_none() && observer.is_none() && timeout.is_none() {
+        return status_inherited(command);
+    }
+
+    command.stdout(Stdio::piped());
+    if label.is_some() || timeout.is_some() {
+        preserve_colors(command);
+        command.stderr(Stdio::piped());
+    }
+
+    signal::forward_while(|| status_piped(command, label.as_deref(), observer, timeout))
+}
+
+/// Run `command` with its output inherited from this process
+///
+/// SIGINT and SIGTERM are forwarded to the command as [`signal::forward_while`] describes.
+pub fn status_inherited(command: &mut Command) -> Result<ExitStatus> {
+    signal::forward_while(|| {
+        let mut child = command.spawn_wc()?;
+        // smoelius: Without a timeout, `timeout::wait` always returns `Some`.
+        timeout::wait(&mut child, None).map(Option::unwrap)
+    })
+}
+
+fn status_piped(
+    command: &mut Command,
+    label: Option<&str>,
+    observer: Option<Observer>,
+    timeout: Option<Duration>,
+) -> Result<ExitStatus> {
+    let mut child = command.spawn_wc()?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to get child's stdout"))?;
+    let stderr = child.stderr.take();
+
+    let tail = Mutex::new(VecDeque::new());
+
+    // smoelius: Each of the child's streams is copied to the corresponding stream of this process so
+    // that, e.g., the child's warnings still go to stderr.
+    let status = thread::scope(|scope| {
+        let stdout_handle =
+            scope.spawn(|| copy_lines(stdout, std::io::stdout(), label, observer, &tail));
+        let stderr_handle = stderr.map(|stderr| {
+            scope.spawn(|| copy_lines(stderr, std::io::stderr(), label, None, &tail))
+        });
+        let status = timeout::wait(&mut child, timeout)?;
+        for handle in std::iter::once(stdout_handle).chain(stderr_handle) {
+            handle
+                .join()
+                .map_err(|_| anyhow!("thread copying child's output panicked"))??;
+        }
+        Ok::<_, anyhow::Error>(status)
+    })?;
+
+    let Some(status) = status else {
+        let tail = tail.into_inner().unwrap();
+        let mut msg = format!(
+            "command timed out after {}: {command:?}",
+            timeout::format_duration(timeout.unwrap_or_default())
+        );
+        if !tail.is_empty() {
+            msg.push_str("\n\nLast lines of output:\n");
+            for line in tail {
+                msg.push_str(&String::from_utf8_lossy(&line));
+                msg.push('\n');
+            }
+        }
+        bail!(msg);
+    };
+
+    Ok(status)
+}
+
+// smoelius: Cargo disables colors when its output is not a terminal, which is the case when its
+// output is piped. So if this process's stderr is a terminal and the user has not expressed a
+// preference, ask for colors explicitly.
+fn preserve_colors(command: &mut Command) {
+    if std::io::stderr().is_terminal() && var_wc("CARGO_TERM_COLOR").is_err() {
+        command.env("CARGO_TERM_COLOR", "always");
+    }
+}
+
+fn copy_lines(
+    reader: impl Read,
+    mut writer: impl IsTerminal + Write,
+    label: Option<&str>,
+    mut observer: Option<Observer>,
+    tail: &Mutex<VecDeque<Vec<u8>>>,
+) -> Result<()> {
+    let prefix = label.map_or_else(String::new, |label| {
+        let style = if writer.is_terminal() {
+            Style::new().bold()
+        } else {
+            Style::new()
+        };
+        format!("{style}[{label}]{style:#} ")
+    });
+    for result in BufReader::new(reader).split(b'\n') {
+        let line = result?;
+        if let Some(observer) = observer.as_mut() {
+            observer(&line);
+        }
+        // smoelius: Write each line with a single call so that lines from different threads are not
+        // interleaved.
+        let mut buf = prefix.as_bytes().to_vec();
+        buf.extend_from_slice(&line);
+        buf.push(b'\n');
+        writer.write_all_wc(&buf)?;
+        let mut tail = tail.lock().unwrap();
+        if tail.len() >= TAIL_LEN {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use anyhow::{Result, bail};
+use crate::Error;
+use anyhow::Result;
 use elaborate::std::env::var_wc;
 
 pub fn check_reentrancy_guard() -> Result<bool> {
@@ -9,7 +10,10 @@ pub fn check_reentrancy_guard() -> Result<bool> {
         if enabled(&dependent) {
             return Ok(true);
         }
-        bail!("cycle detected: cannot run on nested workspaces");
+        return Err(Error::CycleDetected {
+            package: var_wc("CARGO_PKG_NAME")?,
+        }
+        .into());
     }
 
     Ok(false)
@@ -0,0 +1,109 @@
+use crate::Source;
+use elaborate::std::ffi::OsStrContext;
+use serde::Deserialize;
+use std::process::Command;
+
+/// Variables removed from the environment of every command
+///
+/// For example, `RUSTUP_TOOLCHAIN` would cause a nested workspace's `rust-toolchain` file to be
+/// ignored.
+const ALWAYS_REMOVED: &[&str] = &["CARGO", "RUSTC", "RUSTUP_TOOLCHAIN"];
+
+/// Variables Cargo sets for both build scripts and tests, which would otherwise leak into the
+/// nested Cargo commands and change their fingerprints
+///
+/// A trailing `*` matches any suffix.
+const CARGO_INJECTED: &[&str] = &[
+    "CARGO_BIN_EXE_*",
+    "CARGO_BIN_NAME",
+    "CARGO_CRATE_NAME",
+    "CARGO_MANIFEST_DIR",
+    "CARGO_MANIFEST_PATH",
+    "CARGO_PKG_*",
+    "CARGO_PRIMARY_PACKAGE",
+    "CARGO_RUSTC_CURRENT_DIR",
+    "CARGO_TARGET_TMPDIR",
+];
+
+/// Variables Cargo sets for build scripts only
+///
+/// In a test, these variables can only have come from the user's environment. So they are removed
+/// only when running from a build script.
+const CARGO_INJECTED_BUILD_SCRIPT: &[&str] = &[
+    "CARGO_CFG_*",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_FEATURE_*",
+    "CARGO_MAKEFLAGS",
+    "CARGO_MANIFEST_LINKS",
+    "DEBUG",
+    "DEP_*",
+    "HOST",
+    "NUM_JOBS",
+    "OPT_LEVEL",
+    "OUT_DIR",
+    "PROFILE",
+    "RUSTC_LINKER",
+    "RUSTC_WORKSPACE_WRAPPER",
+    "RUSTC_WRAPPER",
+    "RUSTDOC",
+    "TARGET",
+];
+
+/// Adjustments to which environment variables are passed to commands run on nested workspaces
+///
+/// Set in the `env` table of the containing package's `nested_workspace` metadata. Each entry is
+/// a variable name, optionally ending in `*` to match any suffix.
+#[derive(Clone, Default, Deserialize)]
+pub struct EnvPolicy {
+    /// Variables to pass through even though they would be removed by default
+    #[serde(default)]
+    allow: Vec<String>,
+    /// Variables to remove in addition to those removed by default
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+/// Remove variables from `command`'s environment according to `source` and `policy`
+///
+/// The variables in [`ALWAYS_REMOVED`] are removed from every command. The variables in
+/// [`CARGO_INJECTED`] are removed when running from a build script or test, and those in
+/// [`CARGO_INJECTED_BUILD_SCRIPT`] are removed when running from a build script. `cargo nested` is
+/// excluded from the latter two because its environment is the user's.
+pub fn sanitize(command: &mut Command, source: Source, policy: &EnvPolicy) {
+    let allowed = |key: &str| policy.allow.iter().any(|pattern| matches(pattern, key));
+
+    for key in ALWAYS_REMOVED {
+        if !allowed(key) {
+            command.env_remove(key);
+        }
+    }
+
+    for (key, _) in std::env::vars_os() {
+        let Ok(key) = key.to_str_wc() else {
+            continue;
+        };
+        if allowed(key) {
+            continue;
+        }
+        let injected = injected(source).any(|pattern| matches(pattern, key));
+        let denied = policy.deny.iter().any(|pattern| matches(pattern, key));
+        if injected || denied {
+            command.env_remove(key);
+        }
+    }
+}
+
+fn injected(source: Source) -> impl Iterator<Item = &'static str> {
+    let (injected, injected_build_script): (&[&str], &[&str]) = match source {
+        Source::BuildScript => (CARGO_INJECTED, CARGO_INJECTED_BUILD_SCRIPT),
+        Source::Test => (CARGO_INJECTED, &[]),
+        Source::CargoNested => (&[], &[]),
+    };
+    injected.iter().chain(injected_build_script).copied()
+}
+
+fn matches(pattern: &str, key: &str) -> bool {
+    pattern
+        .strip_suffix('*')
+        .map_or(pattern == key, |prefix| key.starts_with(prefix))
+}
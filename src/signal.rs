@@ -0,0 +1,185 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicI32, Ordering},
+};
+
+// smoelius: `PENDING` is written by the signal handler and cleared once the signal has been
+// forwarded. `RECEIVED` remembers the most recent signal so that this process can exit once the
+// children have. Both are cleared when the handlers are restored.
+static PENDING: AtomicI32 = AtomicI32::new(0);
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+// smoelius: `forward_while` can be called from several threads at once, e.g., by tests generated
+// by `tests!`, which the test harness runs in parallel. So the handlers are installed by the first
+// call and restored by the last, and a received signal is forwarded to every registered child.
+static STATE: Mutex<State> = Mutex::new(State {
+    installations: 0,
+    previous: None,
+    children: Vec::new(),
+});
+
+struct State {
+    installations: usize,
+    previous: Option<imp::Previous>,
+    children: Vec<u32>,
+}
+
+/// Run `f`, which is expected to spawn and wait for a child, while forwarding SIGINT and SIGTERM
+///
+/// Rather than terminating this process, SIGINT and SIGTERM are recorded so that the loop waiting
+/// for the child can forward them with [`take_pending`]. If either signal was received, this
+/// process exits once `f` and any concurrent calls return, with the conventional status of 128 plus
+/// the signal number.
+///
+/// Without this, an interrupted parent Cargo command could exit while, e.g., a nested `cargo build`
+/// continues to run and hold the build directory lock.
+pub fn forward_while<T>(f: impl FnOnce() -> T) -> T {
+    let installation = Installation::new();
+    let value = f();
+    if let Some(signal) = installation.finish() {
+        std::process::exit(128 + signal);
+    }
+    value
+}
+
+/// Register `id` as a child to which received signals are forwarded, until the returned value is
+/// dropped
+pub fn register(id: u32) -> Registration {
+    STATE.lock().unwrap().children.push(id);
+    Registration(id)
+}
+
+pub struct Registration(u32);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        if let Some(index) = state.children.iter().position(|&id| id == self.0) {
+            state.children.swap_remove(index);
+        }
+    }
+}
+
+/// Return the signal received since the last call, if any, as a [`sysinfo::Signal`], along with
+/// the registered children to forward it to
+pub fn take_pending() -> Option<(sysinfo::Signal, Vec<u32>)> {
+    let signal = PENDING.swap(0, Ordering::SeqCst);
+    if signal == 0 {
+        return None;
+    }
+    RECEIVED.store(signal, Ordering::SeqCst);
+    let children = STATE.lock().unwrap().children.clone();
+    imp::to_sysinfo(signal).map(|signal| (signal, children))
+}
+
+struct Installation {
+    finished: bool,
+}
+
+impl Installation {
+    fn new() -> Self {
+        let mut state = STATE.lock().unwrap();
+        if state.installations == 0 {
+            state.previous = Some(imp::install());
+        }
+        state.installations += 1;
+        Self { finished: false }
+    }
+
+    /// Uninstall, and return the signal received, if this was the last installation
+    fn finish(mut self) -> Option<i32> {
+        self.finished = true;
+        uninstall()
+    }
+}
+
+// smoelius: If `f` panics, the handlers must still be restored.
+impl Drop for Installation {
+    fn drop(&mut self) {
+        if !self.finished {
+            uninstall();
+        }
+    }
+}
+
+fn uninstall() -> Option<i32> {
+    let mut state = STATE.lock().unwrap();
+    state.installations -= 1;
+    if state.installations != 0 {
+        return None;
+    }
+    if let Some(previous) = state.previous.take() {
+        imp::restore(previous);
+    }
+    // smoelius: A signal that arrived after the last child exited was never forwarded, but it
+    // should still cause this process to exit.
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    let received = RECEIVED.swap(0, Ordering::SeqCst);
+    [received, pending].into_iter().find(|&signal| signal != 0)
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::PENDING;
+    use std::{mem::MaybeUninit, ptr::null_mut, sync::atomic::Ordering};
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    pub type Previous = Vec<(libc::c_int, libc::sigaction)>;
+
+    extern "C" fn handler(signal: libc::c_int) {
+        PENDING.store(signal, Ordering::SeqCst);
+    }
+
+    pub fn install() -> Previous {
+        let mut previous = Vec::new();
+        for signal in SIGNALS {
+            // smoelius: `SA_RESTART` keeps, e.g., the threads copying a child's output from seeing
+            // `EINTR`.
+            // SAFETY: `sigaction` is a C struct for which all zero bytes is a valid value.
+            let mut action = unsafe { MaybeUninit::<libc::sigaction>::zeroed().assume_init() };
+            action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            // SAFETY: Same as above.
+            let mut old = unsafe { MaybeUninit::<libc::sigaction>::zeroed().assume_init() };
+            // SAFETY: Both pointers refer to live `sigaction` structs, and `handler` only performs
+            // an atomic store, which is async-signal-safe.
+            if unsafe { libc::sigaction(signal, &raw const action, &raw mut old) } == 0 {
+                previous.push((signal, old));
+            }
+        }
+        previous
+    }
+
+    pub fn restore(previous: Previous) {
+        for (signal, old) in previous {
+            // SAFETY: `old` was filled in by a successful call to `sigaction` in `install`.
+            unsafe {
+                libc::sigaction(signal, &raw const old, null_mut());
+            }
+        }
+    }
+
+    pub fn to_sysinfo(signal: libc::c_int) -> Option<sysinfo::Signal> {
+        match signal {
+            libc::SIGINT => Some(sysinfo::Signal::Interrupt),
+            libc::SIGTERM => Some(sysinfo::Signal::Term),
+            _ => None,
+        }
+    }
+}
+
+// smoelius: On Windows, Ctrl-C is delivered to every process attached to the console, so there is
+// nothing to forward.
+#[cfg(not(unix))]
+mod imp {
+    pub type Previous = ();
+
+    pub fn install() -> Previous {}
+
+    pub fn restore((): Previous) {}
+
+    pub fn to_sysinfo(_signal: i32) -> Option<sysinfo::Signal> {
+        None
+    }
+}
@@ -0,0 +1,106 @@
+use crate::NestedWorkspaceRoot;
+use anyhow::Result;
+use elaborate::std::{env::var_wc, fs::write_wc, path::PathContext};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    path::{Component, Path, PathBuf},
+};
+
+// smoelius: `tests!` cannot refer to this constant, so keep the two in sync.
+const TESTS_FILENAME: &str = "nested_workspace_tests.rs";
+
+/// Expand to one `#[test]` per nested workspace root
+///
+/// Each test runs `cargo test` on one root and is named after the root's path. Hence, each root
+/// passes or fails independently, and `cargo test <filter>` can select individual roots.
+///
+/// The roots are determined when the containing package's build script runs. So the containing
+/// package must have a build script that calls [`build`](crate::build) and
+/// [`Builder::tests`](crate::Builder::tests).
+///
+/// Example:
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     nested_workspace::build().tests().unwrap();
+/// }
+///
+/// // src/lib.rs
+/// #[cfg(test)]
+/// nested_workspace::tests!();
+/// ```
+#[macro_export]
+macro_rules! tests {
+    () => {
+        include!(concat!(env!("OUT_DIR"), "/nested_workspace_tests.rs"));
+    };
+}
+
+/// Write the file included by [`tests!`] to `OUT_DIR`
+///
+/// If the roots could not be determined, the file contains a `compile_error!` so that the error is
+/// reported only if [`tests!`] is actually used.
+pub fn write_tests(roots: Result<&[NestedWorkspaceRoot], &anyhow::Error>) -> Result<()> {
+    let out_dir = var_wc("OUT_DIR")?;
+    let path = PathBuf::from(out_dir).join(TESTS_FILENAME);
+    let contents = match roots {
+        Ok(roots) => {
+            let cargo_manifest_dir = var_wc("CARGO_MANIFEST_DIR")?;
+            tests_source(Path::new(&cargo_manifest_dir), roots)
+        }
+        Err(error) => format!("compile_error!({:?});\n", format!("{error:#}")),
+    };
+    write_wc(path, contents)
+}
+
+fn tests_source(cargo_manifest_dir: &Path, roots: &[NestedWorkspaceRoot]) -> String {
+    let mut names = HashSet::new();
+    let mut source = String::new();
+    for root in roots {
+        let relative_path = root
+            .path
+            .strip_prefix_wc(cargo_manifest_dir)
+            .unwrap_or(&root.path);
+        let name = test_name(relative_path);
+        let mut unique_name = name.clone();
+        let mut i = 1;
+        while !names.insert(unique_name.clone()) {
+            i += 1;
+            unique_name = format!("{name}_{i}");
+        }
+        writeln!(
+            source,
+            "#[test]
+fn {unique_name}() {{
+    ::nested_workspace::test().root({:?}).unwrap();
+}}",
+            root.path.to_string_lossy()
+        )
+        .unwrap();
+    }
+    source
+}
+
+/// Convert `path` into a valid Rust identifier, e.g., `workspaces/a-b` becomes `workspaces_a_b`
+fn test_name(path: &Path) -> String {
+    let mut name = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(component) => Some(component.to_string_lossy().into_owned()),
+            Component::ParentDir => Some(String::from("parent")),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => None,
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if name.is_empty() {
+        name.push_str("root");
+    } else if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
@@ -0,0 +1,124 @@
+use crate::signal;
+use anyhow::{Context, Result, bail};
+use elaborate::std::{env::var_wc, process::ChildContext, time::InstantContext};
+use std::{
+    process::{Child, ExitStatus},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, Signal, System};
+
+pub const TIMEOUT_ENV: &str = "NESTED_WORKSPACE_TIMEOUT";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Get the timeout selected by [`TIMEOUT_ENV`], if any
+///
+/// When set, the timeout overrides any set in the nested workspace metadata.
+pub fn from_env() -> Result<Option<Duration>> {
+    let Ok(value) = var_wc(TIMEOUT_ENV) else {
+        return Ok(None);
+    };
+    parse_duration(&value).map(Some)
+}
+
+/// Parse a duration like `90`, `90s`, `10m`, or `1h`
+///
+/// A number without a unit is in seconds. The supported units are `ms`, `s`, `m`, and `h`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let index = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(index);
+    let Ok(number) = number.parse::<u64>() else {
+        bail!("invalid duration `{s}`; expected a number followed by `ms`, `s`, `m`, or `h`");
+    };
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "" | "s" => Some(number),
+        "m" => number.checked_mul(60),
+        "h" => number.checked_mul(60 * 60),
+        _ => bail!("invalid duration `{s}`; expected a number followed by `ms`, `s`, `m`, or `h`"),
+    };
+    let Some(secs) = secs else {
+        bail!("duration `{s}` is too large");
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// Format `duration` as [`parse_duration`] would accept it
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration.subsec_millis() != 0 || secs == 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs.is_multiple_of(60 * 60) {
+        format!("{}h", secs / (60 * 60))
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Wait for `child` to exit, or kill it and its descendants once `timeout` has elapsed
+///
+/// Signals received while waiting (see [`signal::forward_while`]) are forwarded to `child` and its
+/// descendants, as well as to any other children being waited for.
+///
+/// Returns `None` if `child` was killed.
+pub fn wait(child: &mut Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    let deadline = timeout
+        .map(|timeout| {
+            Instant::now()
+                .checked_add_wc(timeout)
+                .with_context(|| format!("timeout `{}` is too large", format_duration(timeout)))
+        })
+        .transpose()?;
+    let _registration = signal::register(child.id());
+    loop {
+        if let Some(status) = child.try_wait_wc()? {
+            return Ok(Some(status));
+        }
+        if let Some((signal, ids)) = signal::take_pending() {
+            for id in ids {
+                signal_tree(id, signal);
+            }
+        }
+        let now = Instant::now();
+        let Some(deadline) = deadline else {
+            sleep(POLL_INTERVAL);
+            continue;
+        };
+        if now >= deadline {
+            break;
+        }
+        sleep(POLL_INTERVAL.min(deadline - now));
+    }
+    signal_tree(child.id(), Signal::Kill);
+    // smoelius: Reap the child so that it does not linger as a zombie.
+    child.wait_wc()?;
+    Ok(None)
+}
+
+// smoelius: Signaling only the child would leave, e.g., the test binaries that `cargo test` spawned
+// running. Moreover, they would keep the child's stdout and stderr open, so the threads reading
+// them would not finish.
+fn signal_tree(id: u32, signal: Signal) {
+    let mut system = System::new();
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing());
+    let mut ids = vec![Pid::from_u32(id)];
+    let mut i = 0;
+    while i < ids.len() {
+        let parent_id = ids[i];
+        ids.extend(
+            system
+                .processes()
+                .iter()
+                .filter_map(|(&id, process)| (process.parent() == Some(parent_id)).then_some(id)),
+        );
+        i += 1;
+    }
+    for id in ids {
+        if let Some(process) = system.process(id) {
+            process.kill_with(signal);
+        }
+    }
+}
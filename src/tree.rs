@@ -0,0 +1,120 @@
+use crate::{NestedWorkspaceRoot, nested_workspace_roots_for_workspace, no_deps_metadata};
+use anyhow::Result;
+use cargo_metadata::Metadata;
+use std::path::{Path, PathBuf};
+
+/// A workspace and all of its nested workspaces, recursively
+pub struct NestedWorkspaceTree {
+    root: PathBuf,
+    metadata: Option<Metadata>,
+    children: Vec<NestedWorkspaceNode>,
+}
+
+/// A nested workspace within a [`NestedWorkspaceTree`]
+pub struct NestedWorkspaceNode {
+    root: NestedWorkspaceRoot,
+    metadata: Option<Metadata>,
+    children: Vec<NestedWorkspaceNode>,
+}
+
+impl NestedWorkspaceTree {
+    /// Discover the workspace containing `dir` and all of its nested workspaces
+    pub fn discover(dir: &Path) -> Result<Self> {
+        Self::discover_impl(dir, false)
+    }
+
+    /// Like [`NestedWorkspaceTree::discover`], but keep each workspace's `cargo metadata --no-deps`
+    /// output
+    ///
+    /// Discovery reads each workspace's metadata anyway, so keeping it costs no additional
+    /// `cargo metadata` commands.
+    pub fn discover_with_metadata(dir: &Path) -> Result<Self> {
+        Self::discover_impl(dir, true)
+    }
+
+    fn discover_impl(dir: &Path, keep_metadata: bool) -> Result<Self> {
+        let metadata = no_deps_metadata(dir)?;
+        let children = discover_children(&metadata, keep_metadata)?;
+        Ok(Self {
+            root: metadata.workspace_root.clone().into_std_path_buf(),
+            metadata: keep_metadata.then_some(metadata),
+            children,
+        })
+    }
+
+    /// Return the root of the workspace containing the directory passed to
+    /// [`NestedWorkspaceTree::discover`]
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Return the workspace's `cargo metadata --no-deps` output, if it was kept
+    #[must_use]
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Return the workspaces nested directly within the workspace
+    #[must_use]
+    pub fn children(&self) -> &[NestedWorkspaceNode] {
+        &self.children
+    }
+
+    /// Return all nested workspaces in depth-first order, each paired with its depth
+    ///
+    /// Workspaces nested directly within the workspace have depth 1, workspaces nested within them
+    /// have depth 2, etc.
+    #[must_use]
+    pub fn nodes(&self) -> Vec<(usize, &NestedWorkspaceNode)> {
+        let mut nodes = Vec::new();
+        collect_nodes(&self.children, 1, &mut nodes);
+        nodes
+    }
+}
+
+impl NestedWorkspaceNode {
+    /// Return the nested workspace's root, which includes the containing package's name and
+    /// manifest path, and the pattern that matched the root
+    #[must_use]
+    pub fn root(&self) -> &NestedWorkspaceRoot {
+        &self.root
+    }
+
+    /// Return the nested workspace's `cargo metadata --no-deps` output, if it was kept
+    #[must_use]
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Return the workspaces nested directly within the nested workspace
+    #[must_use]
+    pub fn children(&self) -> &[NestedWorkspaceNode] {
+        &self.children
+    }
+}
+
+fn discover_children(metadata: &Metadata, keep_metadata: bool) -> Result<Vec<NestedWorkspaceNode>> {
+    let mut children = Vec::new();
+    for root in nested_workspace_roots_for_workspace(metadata)? {
+        let metadata = no_deps_metadata(&root.path)?;
+        let grandchildren = discover_children(&metadata, keep_metadata)?;
+        children.push(NestedWorkspaceNode {
+            root,
+            metadata: keep_metadata.then_some(metadata),
+            children: grandchildren,
+        });
+    }
+    Ok(children)
+}
+
+fn collect_nodes<'a>(
+    children: &'a [NestedWorkspaceNode],
+    depth: usize,
+    nodes: &mut Vec<(usize, &'a NestedWorkspaceNode)>,
+) {
+    for child in children {
+        nodes.push((depth, child));
+        collect_nodes(&child.children, depth + 1, nodes);
+    }
+}
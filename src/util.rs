@@ -3,8 +3,12 @@ use elaborate::std::{env::current_dir_wc, path::PathContext};
 use std::{
     io::{IsTerminal, Write},
     path::Path,
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+static BUILD_SCRIPT: AtomicBool = AtomicBool::new(false);
+
 pub struct Delimiter<'a>(&'a Path);
 
 impl<'a> Delimiter<'a> {
@@ -37,9 +41,6 @@ impl Drop for Delimiter<'_> {
     }
 }
 
-// smoelius: When `__disable_offline_check` is enabled, `StripCurrentDir` appears unused. Hence,
-// this `allow` cannot be `expect`.
-#[allow(dead_code)]
 pub trait StripCurrentDir {
     fn strip_current_dir(&self) -> &Self;
 }
@@ -52,3 +53,29 @@ impl StripCurrentDir for Path {
         self.strip_prefix_wc(current_dir).unwrap_or(self)
     }
 }
+
+/// Return `command`'s program and arguments separated by spaces
+pub fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Record that the current process is a build script, so that [`warn`] emits `cargo::warning`s
+pub fn set_build_script() {
+    BUILD_SCRIPT.store(true, Ordering::Relaxed);
+}
+
+/// Emit `message` as a warning
+///
+/// In a build script, the warning is emitted as a `cargo::warning`, as Cargo does not show a build
+/// script's stderr unless `-vv` is passed.
+pub fn warn(message: &str) -> std::io::Result<()> {
+    if BUILD_SCRIPT.load(Ordering::Relaxed) {
+        writeln!(std::io::stdout(), "cargo::warning={message}")
+    } else {
+        writeln!(std::io::stderr(), "Warning: {message}")
+    }
+}
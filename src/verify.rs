@@ -0,0 +1,142 @@
+use crate::{
+    MetadataRoot, depends_on, nested_workspace_metadata, no_deps_metadata, util::StripCurrentDir,
+};
+use anyhow::{Result, bail};
+use cargo_metadata::{Package, camino::Utf8Path};
+use glob::glob;
+use std::{io::Write, path::Path};
+
+/// Check the `nested_workspace` metadata of the workspace containing `dir` and of all nested
+/// workspaces, recursively
+///
+/// Each problem is reported on stderr along with the manifest path and root entry it concerns.
+/// Fails if any problems are found.
+pub fn verify(dir: &Path) -> Result<()> {
+    let mut verifier = Verifier { n_problems: 0 };
+    verifier.verify_workspace(dir)?;
+    let n_problems = verifier.n_problems;
+    if n_problems != 0 {
+        bail!("found {n_problems} problem(s) in `nested_workspace` metadata");
+    }
+    println!("No problems found in `nested_workspace` metadata");
+    Ok(())
+}
+
+struct Verifier {
+    n_problems: usize,
+}
+
+impl Verifier {
+    fn verify_workspace(&mut self, dir: &Path) -> Result<()> {
+        let cargo_metadata = no_deps_metadata(dir)?;
+        for package in &cargo_metadata.packages {
+            let metadata = match nested_workspace_metadata(package) {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => continue,
+                Err(error) => {
+                    self.report(
+                        package,
+                        None,
+                        &format!("invalid `nested_workspace` metadata: {error}"),
+                    )?;
+                    continue;
+                }
+            };
+            let Some(manifest_dir) = package.manifest_path.parent() else {
+                bail!(
+                    "failed to get manifest dir from `{}`",
+                    package.manifest_path
+                );
+            };
+            for root in &metadata.roots {
+                if let Err(error) = root.timeout() {
+                    self.report(package, Some(root), &error.to_string())?;
+                }
+                let paths = match glob(&format!("{manifest_dir}/{}", root.path())) {
+                    Ok(paths) => paths.collect::<Result<Vec<_>, _>>()?,
+                    Err(error) => {
+                        self.report(package, Some(root), &format!("invalid pattern: {error}"))?;
+                        continue;
+                    }
+                };
+                if paths.is_empty() && !root.optional() {
+                    self.report(package, Some(root), "pattern matches nothing")?;
+                }
+                for path in paths {
+                    match verify_root(package, manifest_dir, root, &path)? {
+                        Some(message) => self.report(package, Some(root), &message)?,
+                        None => self.verify_workspace(&path)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn report(
+        &mut self,
+        package: &Package,
+        root: Option<&MetadataRoot>,
+        message: &str,
+    ) -> Result<()> {
+        self.n_problems += 1;
+        let root = root.map_or_else(String::new, |root| format!(": root `{}`", root.path()));
+        writeln!(
+            std::io::stderr(),
+            "error: {}{root}: {message}",
+            package
+                .manifest_path
+                .as_std_path()
+                .strip_current_dir()
+                .display()
+        )?;
+        Ok(())
+    }
+}
+
+/// Check the nested workspace root at `path`, which `root` in `package`'s metadata matched
+///
+/// Returns a description of the problem, if any.
+fn verify_root(
+    package: &Package,
+    manifest_dir: &Utf8Path,
+    root: &MetadataRoot,
+    path: &Path,
+) -> Result<Option<String>> {
+    let path_display = path.strip_current_dir().display().to_string();
+    let cargo_metadata = match no_deps_metadata(path) {
+        Ok(cargo_metadata) => cargo_metadata,
+        Err(error) => {
+            return Ok(Some(format!(
+                "`cargo metadata` failed in `{path_display}`: {error}"
+            )));
+        }
+    };
+    if dunce::canonicalize(path)? != cargo_metadata.workspace_root {
+        return Ok(Some(format!(
+            "`{path_display}` is not a workspace root but is part of the workspace at `{}`; add a \
+             `[workspace]` table to its manifest and, if necessary, add it to the containing \
+             workspace's `exclude`",
+            cargo_metadata.workspace_root
+        )));
+    }
+    if root.dependent() {
+        return Ok(None);
+    }
+    // smoelius: A dev-dependency affects only `cargo test` run on the root, e.g., by
+    // `nested_workspace::test()`, so it is reported separately.
+    if depends_on(&cargo_metadata, manifest_dir.as_std_path(), false)? {
+        return Ok(Some(format!(
+            "`{path_display}` depends on `{}`, but `dependent = true` is not set",
+            package.name
+        )));
+    }
+    if depends_on(&cargo_metadata, manifest_dir.as_std_path(), true)? {
+        return Ok(Some(format!(
+            "`{path_display}` has a dev-dependency on `{}`, which `cargo test` would compile when \
+             the root is tested, but `dependent = true` is not set",
+            package.name
+        )));
+    }
+    Ok(None)
+}
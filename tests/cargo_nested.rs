@@ -1,7 +1,18 @@
 use assert_cmd::assert::OutputAssertExt;
 use cargo_metadata::MetadataCommand;
-use elaborate::std::process::CommandContext;
-use std::{path::PathBuf, process::Command, sync::LazyLock};
+use elaborate::std::{
+    fs::{create_dir_all_wc, read_to_string_wc, write_wc},
+    io::BufReadContext,
+    path::PathContext,
+    process::{ChildContext, CommandContext, ExitStatusContext},
+};
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 use tempfile::tempdir;
 
 static CARGO_NESTED: LazyLock<PathBuf> = LazyLock::new(|| {
@@ -16,6 +27,30 @@ static CARGO_NESTED: LazyLock<PathBuf> = LazyLock::new(|| {
         .into_std_path_buf()
 });
 
+#[test]
+fn cargo_metadata_invocations() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "--dry-run", "clean"])
+        .current_dir("fixtures/multilayer")
+        .env("RUST_LOG", "nested_workspace=debug")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success();
+
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+    // smoelius: One invocation for each of the three workspaces.
+    assert_eq!(
+        3,
+        stderr
+            .lines()
+            .filter(|line| line.contains("running `cargo metadata --no-deps`"))
+            .count(),
+        "{stderr}"
+    );
+}
+
 #[test]
 fn check_does_not_warn_about_offline() {
     let target_dir = tempdir().unwrap();
@@ -34,6 +69,245 @@ fn check_does_not_warn_about_offline() {
     assert!(!stderr.contains("Since `--offline` was not passed"));
 }
 
+#[test]
+fn dry_run() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "--dry-run", "clean"])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success().stderr("");
+
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+
+    let single_layer_workspace = Path::new("workspace")
+        .join("single_layer")
+        .join("workspace");
+
+    let lines = stdout
+        .lines()
+        .filter(|line| line.starts_with("Would run"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        [
+            String::from("Would run `cargo clean` in `.`"),
+            String::from("Would run `cargo clean` in `workspace`"),
+            format!(
+                "Would run `cargo clean` in `{}`",
+                single_layer_workspace.display()
+            ),
+        ]
+        .as_slice(),
+        lines.as_slice()
+    );
+    assert!(stdout.contains("    set NESTED_WORKSPACE_CARGO_NESTED=1\n"));
+}
+
+#[test]
+fn exec() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args([
+            "nested",
+            "exec",
+            "--",
+            "cargo",
+            "locate-project",
+            "--workspace",
+            "--message-format",
+            "plain",
+        ])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success();
+
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+
+    let lines = stdout.lines().map(Path::new).collect::<Vec<_>>();
+    assert_eq!(3, lines.len());
+    assert!(lines[0].ends_with("multilayer/Cargo.toml"));
+    assert!(lines[1].ends_with("multilayer/workspace/Cargo.toml"));
+    assert!(lines[2].ends_with("workspace/single_layer/workspace/Cargo.toml"));
+
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "--dry-run", "exec", "cargo", "locate-project"])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success();
+
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+
+    assert!(stdout.contains("    set NESTED_WORKSPACE_PACKAGE=multilayer\n"));
+    assert!(stdout.contains("    set NESTED_WORKSPACE_PACKAGE=single_layer\n"));
+}
+
+// smoelius: The nested workspace's build script saves the environment of its parent, i.e., the
+// `cargo build` run by the containing package's build script. Cargo would reset variables like
+// `OUT_DIR` for the build script itself, so its own environment would not show whether they were
+// removed.
+#[cfg(target_os = "linux")]
+#[test]
+fn env_policy() {
+    let tempdir = tempdir().unwrap();
+
+    write_containing_package(
+        tempdir.path(),
+        &format!(
+            r#"[build-dependencies]
+{}
+
+[features]
+default = ["foo"]
+foo = []
+
+[package.metadata.nested_workspace]
+roots = ["nested"]
+env = {{ allow = ["CARGO_FEATURE_FOO"] }}
+
+[workspace]
+exclude = ["nested"]
+"#,
+            nested_workspace_dependency()
+        ),
+    );
+    write(tempdir.path(), "build.rs", BUILD_SCRIPT);
+
+    let dir = tempdir.path().join("nested");
+    write_nested_workspace(&dir, "nested");
+    write(
+        &dir,
+        "build.rs",
+        r#"fn main() {
+    let parent_id = std::os::unix::process::parent_id();
+    let environ = std::fs::read(format!("/proc/{parent_id}/environ")).unwrap();
+    std::fs::write("environ", environ).unwrap();
+}
+"#,
+    );
+
+    Command::new("cargo")
+        .args(["build", "--offline"])
+        .current_dir(&tempdir)
+        .assert()
+        .success();
+
+    let environ = read_to_string_wc(dir.join("environ")).unwrap();
+    let vars = environ.split('\0').collect::<Vec<_>>();
+
+    assert!(vars.contains(&"CARGO_FEATURE_FOO=1"), "{vars:#?}");
+    for key in ["CARGO_FEATURE_DEFAULT", "CARGO_PKG_NAME", "OUT_DIR"] {
+        assert!(
+            !vars.iter().any(|var| var.starts_with(&format!("{key}="))),
+            "{vars:#?}"
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_code() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "exec", "--", "sh", "-c", "exit 7"])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().code(7);
+
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+    assert!(stderr.starts_with("error: command failed: "), "{stderr}");
+}
+
+#[test]
+fn fingerprint() {
+    let tempdir = tempdir().unwrap();
+
+    write_containing_package(
+        tempdir.path(),
+        &format!(
+            r#"[build-dependencies]
+{}
+
+[package.metadata.nested_workspace]
+roots = ["nested"]
+fingerprint = true
+
+[workspace]
+exclude = ["nested"]
+"#,
+            nested_workspace_dependency()
+        ),
+    );
+    write(tempdir.path(), "build.rs", BUILD_SCRIPT);
+
+    let dir = tempdir.path().join("nested");
+    write_nested_workspace(&dir, "nested");
+
+    // smoelius: `-vv` causes Cargo to show the build script's log messages.
+    let build = || {
+        let output = Command::new("cargo")
+            .args(["build", "--offline", "-vv"])
+            .env("RUST_LOG", "nested_workspace=debug")
+            .current_dir(&tempdir)
+            .output_wc()
+            .unwrap();
+        let assert = output.assert().success();
+        String::from_utf8_lossy(&assert.get_output().stderr).into_owned()
+    };
+
+    let skipping = format!(
+        "skipping `{}` as it is unchanged",
+        dunce::canonicalize(&dir).unwrap().display()
+    );
+
+    let stderr = build();
+    assert!(!stderr.contains(&skipping), "{stderr}");
+
+    let stderr = build();
+    assert!(stderr.contains(&skipping), "{stderr}");
+
+    // smoelius: Changing the file's size ensures the change is detected even if the modification
+    // time's resolution is coarse.
+    write(&dir, "src/lib.rs", "pub fn foo() {}\n");
+
+    let stderr = build();
+    assert!(!stderr.contains(&skipping), "{stderr}");
+
+    let stderr = build();
+    assert!(stderr.contains(&skipping), "{stderr}");
+}
+
+#[test]
+fn junit() {
+    let target_dir = tempdir().unwrap();
+    let report = target_dir.path().join("report.xml");
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "--junit"])
+        .arg(&report)
+        .arg("test")
+        .env("CARGO_TARGET_DIR", target_dir.path())
+        .current_dir("example")
+        .output_wc()
+        .unwrap();
+    output.assert().success();
+
+    let contents = read_to_string_wc(&report).unwrap();
+
+    for root in ["nested_workspace_a", "nested_workspace_b"] {
+        assert!(
+            contents.contains(&format!(
+                "<testsuite name=\"{root}\" tests=\"1\" failures=\"0\" skipped=\"0\">"
+            )),
+            "{contents}"
+        );
+    }
+}
+
 #[test]
 fn list_cycle() {
     let output = Command::new(&*CARGO_NESTED)
@@ -70,3 +344,512 @@ fn list_no_roots() {
 
     output.assert().success().stdout("").stderr("");
 }
+
+#[test]
+fn lock_report() {
+    let tempdir = tempdir().unwrap();
+
+    write_containing_package(
+        tempdir.path(),
+        r#"[package.metadata.nested_workspace]
+roots = ["a", "b"]
+lock_report = { max_duplicates = 0, ignore = ["libc"] }
+
+[workspace]
+exclude = ["a", "b"]
+"#,
+    );
+    write_lockfile(tempdir.path(), "1.0.190", "0.2.1");
+
+    for root in ["a", "b"] {
+        let dir = tempdir.path().join(root);
+        write_nested_workspace(&dir, root);
+        write_lockfile(&dir, "1.0.219", "0.2.2");
+    }
+
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "lock-report"])
+        .current_dir(&tempdir)
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().failure().stdout(
+        "serde
+    1.0.190: `.`
+    1.0.219: `a`, `b`
+",
+    );
+
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(
+        stderr.contains("found 1 crates resolved to different versions across workspaces"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn metadata() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "metadata", "--no-deps"])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success();
+
+    let value = serde_json::from_slice::<serde_json::Value>(&assert.get_output().stdout).unwrap();
+    let workspaces = value["workspaces"].as_array().unwrap();
+
+    let single_layer_workspace = Path::new("workspace")
+        .join("single_layer")
+        .join("workspace");
+
+    let current_dir = dunce::canonicalize("fixtures/multilayer").unwrap();
+    assert_eq!(
+        vec![
+            (current_dir.clone(), None, 0),
+            (current_dir.join("workspace"), Some("multilayer"), 1),
+            (
+                current_dir.join(single_layer_workspace),
+                Some("single_layer"),
+                2
+            ),
+        ],
+        workspaces
+            .iter()
+            .map(|workspace| (
+                PathBuf::from(workspace["root"].as_str().unwrap()),
+                workspace["package"].as_str(),
+                workspace["depth"].as_u64().unwrap(),
+            ))
+            .collect::<Vec<_>>()
+    );
+    assert!(
+        workspaces
+            .iter()
+            .all(|workspace| workspace["metadata"]["workspace_root"] == workspace["root"])
+    );
+}
+
+#[test]
+fn nested_only_and_no_recurse() {
+    for (option, expected_dirs) in [
+        (
+            "--nested-only",
+            &["workspace", "workspace/single_layer/workspace"][..],
+        ),
+        ("--no-recurse", &["."][..]),
+    ] {
+        let output = Command::new(&*CARGO_NESTED)
+            .args(["nested", "--dry-run", option, "clean"])
+            .current_dir("fixtures/multilayer")
+            .output_wc()
+            .unwrap();
+
+        let assert = output.assert().success();
+
+        let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+
+        let dirs = stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("Would run `cargo clean` in `"))
+            .map(|line| Path::new(line.trim_end_matches('`')))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            expected_dirs.iter().map(Path::new).collect::<Vec<_>>(),
+            dirs,
+            "{option}"
+        );
+    }
+
+    Command::new(&*CARGO_NESTED)
+        .args(["nested", "--nested-only", "--no-recurse", "clean"])
+        .current_dir("fixtures/multilayer")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn output_prefixed() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args([
+            "nested",
+            "--output",
+            "prefixed",
+            "locate-project",
+            "--workspace",
+            "--message-format",
+            "plain",
+        ])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().success();
+
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+    let single_layer_workspace = Path::new("workspace")
+        .join("single_layer")
+        .join("workspace");
+
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(3, lines.len());
+    assert!(!lines[0].starts_with('['));
+    assert!(lines[1].starts_with("[workspace] "));
+    assert!(lines[2].starts_with(&format!("[{}] ", single_layer_workspace.display())));
+    assert!(!stderr.contains("<<<"));
+}
+
+#[test]
+fn parse_cargo_command() {
+    for (args, toolchain, global_args, subcommand, rest, forwardable) in [
+        (
+            &["cargo", "+nightly", "check"][..],
+            Some("nightly"),
+            &[][..],
+            "check",
+            &[][..],
+            &[][..],
+        ),
+        (
+            &["cargo", "-C", "dir", "build", "--release"],
+            None,
+            &["-C", "dir"],
+            "build",
+            &["--release"],
+            &[],
+        ),
+        (
+            &["cargo", "--config", "net.offline=true", "test"],
+            None,
+            &["--config", "net.offline=true"],
+            "test",
+            &[],
+            &["--config", "net.offline=true"],
+        ),
+        (
+            &["cargo", "-Zfoo", "check"],
+            None,
+            &["-Zfoo"],
+            "check",
+            &[],
+            &[],
+        ),
+        (
+            &["cargo", "-v", "test", "--", "--nocapture"],
+            None,
+            &["-v"],
+            "test",
+            &["--", "--nocapture"],
+            &["-v"],
+        ),
+        (
+            &["cargo", "+nightly", "-v", "clippy"],
+            Some("nightly"),
+            &["-v"],
+            "clippy",
+            &[],
+            &["-v"],
+        ),
+    ] {
+        let command = nested_workspace::parse_cargo_command(args)
+            .unwrap()
+            .unwrap();
+        assert_eq!(toolchain, command.toolchain.as_deref(), "{args:?}");
+        assert_eq!(global_args, command.global_args, "{args:?}");
+        assert_eq!(subcommand, command.subcommand.to_string(), "{args:?}");
+        assert_eq!(rest, command.args, "{args:?}");
+        assert_eq!(forwardable, command.forwardable_global_args(), "{args:?}");
+    }
+}
+
+#[test]
+fn select_roots() {
+    let tempdir = tempdir().unwrap();
+
+    write_containing_package(
+        tempdir.path(),
+        &format!(
+            r#"[dev-dependencies]
+{}
+
+[package.metadata.nested_workspace]
+roots = ["a", "b", "c"]
+
+[workspace]
+exclude = ["a", "b", "c"]
+"#,
+            nested_workspace_dependency()
+        ),
+    );
+    write(
+        tempdir.path(),
+        "tests/select_roots.rs",
+        r#"use nested_workspace::{Builder, Error};
+
+#[test]
+fn select_roots() {
+    assert_eq!(["a", "b", "c"], ran(nested_workspace::test()).as_slice());
+    assert_eq!(["b"], ran(nested_workspace::test().root("b")).as_slice());
+    assert_eq!(["a", "c"], ran(nested_workspace::test().exclude_root("b")).as_slice());
+    assert_eq!(
+        ["a"],
+        ran(nested_workspace::test().root("a").root("b").exclude_root("b")).as_slice()
+    );
+    assert_eq!(
+        ["c"],
+        ran(nested_workspace::test().filter(|root| root.path().ends_with("c"))).as_slice()
+    );
+    assert!(matches!(
+        nested_workspace::test().root("d").run(),
+        Err(Error::UnknownRoot { .. })
+    ));
+    assert!(matches!(
+        nested_workspace::test().exclude_root("d").run(),
+        Err(Error::UnknownRoot { .. })
+    ));
+}
+
+fn ran(builder: Builder) -> Vec<String> {
+    builder
+        .run()
+        .unwrap()
+        .ran()
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect()
+}
+"#,
+    );
+
+    for name in ["a", "b", "c"] {
+        write_nested_workspace(&tempdir.path().join(name), name);
+    }
+
+    Command::new("cargo")
+        .args(["test", "--offline"])
+        .current_dir(&tempdir)
+        .assert()
+        .success();
+}
+
+// smoelius: `sh` prints a line and then execs `sleep`, so once the line has been read, the signal is
+// sent while `cargo nested` is waiting for its child.
+#[cfg(unix)]
+#[test]
+fn signal_forwarded() {
+    let mut child = Command::new(&*CARGO_NESTED)
+        .args([
+            "nested",
+            "exec",
+            "--",
+            "sh",
+            "-c",
+            "echo started; exec sleep 60",
+        ])
+        .current_dir("fixtures/multilayer")
+        .stdout(Stdio::piped())
+        .spawn_wc()
+        .unwrap();
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line_wc(&mut line)
+        .unwrap();
+    assert_eq!("started\n", line);
+
+    let start = Instant::now();
+
+    Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+
+    let status = child.wait_wc().unwrap();
+
+    assert_eq!(128 + 15, status.code_wc().unwrap());
+    assert!(start.elapsed() < Duration::from_secs(30));
+}
+
+#[test]
+fn timeout() {
+    let output = Command::new(&*CARGO_NESTED)
+        .args(["nested", "--timeout", "1ms", "locate-project"])
+        .current_dir("fixtures/multilayer")
+        .output_wc()
+        .unwrap();
+
+    let assert = output.assert().failure();
+
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+    assert!(stderr.contains("command timed out after 1ms"), "{stderr}");
+
+    // smoelius: The first duration overflows when converted to seconds. The second does not, but it
+    // overflows when added to the current time.
+    for (timeout, expected) in [
+        (
+            "18446744073709551615h",
+            "duration `18446744073709551615h` is too large",
+        ),
+        (
+            "18446744073709551615s",
+            "timeout `18446744073709551615s` is too large",
+        ),
+    ] {
+        let output = Command::new(&*CARGO_NESTED)
+            .args(["nested", "--timeout", timeout, "locate-project"])
+            .current_dir("fixtures/multilayer")
+            .output_wc()
+            .unwrap();
+
+        let assert = output.assert().failure();
+
+        let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+
+        assert!(stderr.contains(expected), "{stderr}");
+    }
+}
+
+#[test]
+fn unmatched_root() {
+    for strict in [false, true] {
+        let tempdir = tempdir().unwrap();
+
+        write_containing_package(
+            tempdir.path(),
+            &format!(
+                r#"[package.metadata.nested_workspace]
+roots = ["missing", {{ path = "optional", optional = true }}]
+strict = {strict}
+"#
+            ),
+        );
+
+        let output = Command::new(&*CARGO_NESTED)
+            .args(["nested", "--list"])
+            .current_dir(&tempdir)
+            .output_wc()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(!strict, output.status.success(), "{stderr}");
+        // smoelius: The manifest path is not necessarily relative, e.g., if the temporary directory's
+        // path involves a symlink.
+        assert!(stderr.contains("root `missing` in `"), "{stderr}");
+        assert!(stderr.contains("Cargo.toml` matches nothing"), "{stderr}");
+        assert!(!stderr.contains("optional"), "{stderr}");
+    }
+}
+
+#[test]
+fn verify() {
+    for (fixture, expected_problem) in [
+        (
+            "cycle",
+            Some(
+                "root `dependent`: `dependent` depends on `cycle`, but `dependent = true` is not set",
+            ),
+        ),
+        ("cycle_with_dependent", None),
+        ("multilayer", None),
+        (
+            "root_with_no_workspace",
+            Some("root `empty`: `empty` is not a workspace root"),
+        ),
+    ] {
+        let output = Command::new(&*CARGO_NESTED)
+            .args(["nested", "verify"])
+            .current_dir(Path::new("fixtures").join(fixture))
+            .output_wc()
+            .unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if let Some(expected_problem) = expected_problem {
+            assert!(!output.status.success(), "{fixture}");
+            assert!(stderr.contains(expected_problem), "{fixture}: {stderr}");
+        } else {
+            assert!(output.status.success(), "{fixture}: {stderr}");
+        }
+    }
+}
+
+// smoelius: The following helpers write packages to temporary directories, for tests that modify
+// the packages or that need a build script's environment. Packages that can be tested as they are
+// belong in `fixtures`.
+
+const BUILD_SCRIPT: &str = "fn main() {
+    nested_workspace::build().unwrap();
+}
+";
+
+/// Write a package named `containing` to `dir`, with `manifest` following its `[package]` table
+fn write_containing_package(dir: &Path, manifest: &str) {
+    write(
+        dir,
+        "Cargo.toml",
+        &format!(
+            r#"[package]
+name = "containing"
+version = "0.1.0"
+edition = "2024"
+
+{manifest}"#
+        ),
+    );
+    write(dir, "src/lib.rs", "");
+}
+
+/// Write a workspace containing a single, empty package named `name` to `dir`
+fn write_nested_workspace(dir: &Path, name: &str) {
+    write(
+        dir,
+        "Cargo.toml",
+        &format!(
+            r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2024"
+
+[workspace]
+"#
+        ),
+    );
+    write(dir, "src/lib.rs", "");
+}
+
+/// Return a dependency on this crate, for use in a package's manifest
+fn nested_workspace_dependency() -> String {
+    format!(
+        "nested_workspace = {{ path = {:?} }}",
+        env!("CARGO_MANIFEST_DIR")
+    )
+}
+
+fn write(dir: &Path, path: &str, contents: &str) {
+    let path = dir.join(path);
+    create_dir_all_wc(path.parent_wc().unwrap()).unwrap();
+    write_wc(path, contents).unwrap();
+}
+
+fn write_lockfile(dir: &Path, serde_version: &str, libc_version: &str) {
+    let contents = format!(
+        r#"version = 4
+
+[[package]]
+name = "libc"
+version = "{libc_version}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "serde"
+version = "{serde_version}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#
+    );
+    write(dir, "Cargo.lock", &contents);
+}